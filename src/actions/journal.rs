@@ -0,0 +1,133 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::Algorithm;
+use crate::output::json::{raw_path, raw_path_option};
+use crate::output::utc_timestamp;
+use crate::walk::FileEntry;
+
+//-------------------------------------------------------------------------------------------------
+//  every change is written as an intent before the file is touched and once more when it is done
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    Intent,
+    Done,
+}
+
+//  one change to the filesystem, `kept` is the file with the same content that stayed and `hash`
+//  the digest of that content by `algorithm`; `size` and `mtime` describe the file at `path` as
+//  it was before
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub time       : String,
+    pub step       : Step,
+    pub action     : String,
+    #[serde(with = "raw_path")]
+    pub path       : PathBuf,
+    #[serde(with = "raw_path")]
+    pub kept       : PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "raw_path_option")]
+    pub target     : Option<PathBuf>,
+    pub algorithm  : String,
+    pub hash       : String,
+    pub size       : u64,
+    pub mtime      : i64,
+    pub mtime_nsec : u32,
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+//  YYYYMMDD-hhmmss for file names
+pub fn stamp() -> String {
+    utc_timestamp(now())
+        .chars()
+        .filter_map(|c| match c {
+            ' '       => Some('-'),
+            '-' | ':' => None,
+            c         => Some(c),
+        })
+        .collect()
+}
+
+//  lsdups-journal-YYYYMMDD-hhmmss.ndjson in the current directory
+pub fn default_path() -> PathBuf {
+    PathBuf::from(format!("lsdups-journal-{}.ndjson", stamp()))
+}
+
+//-------------------------------------------------------------------------------------------------
+//  journals are read back from anywhere, so relative paths are written out from the current
+//  directory
+pub fn absolute_path(path : &Path) -> PathBuf {
+    env::current_dir()
+        .unwrap_or_default()
+        .join(path)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+//-------------------------------------------------------------------------------------------------
+//  every step is appended as one json line and synced before the file is touched, so a run cut
+//  short at any point leaves a journal naming every file it may have changed
+pub struct Journal {
+    file      : File,
+    algorithm : Algorithm,
+}
+
+impl Journal {
+    pub fn open(path : &Path, algorithm : Algorithm) -> io::Result<Journal> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { file, algorithm })
+    }
+
+    pub fn record(&mut self, step : Step, action : &str, e : &FileEntry, kept : &FileEntry, target : Option<&Path>, hash : &str) -> io::Result<()> {
+        let entry = Entry {
+            time       : utc_timestamp(now()),
+            step,
+            action     : action.to_string(),
+            path       : absolute_path(&e.path),
+            kept       : absolute_path(&kept.path),
+            target     : target.map(Path::to_path_buf),
+            algorithm  : self.algorithm.name().to_string(),
+            hash       : hash.to_string(),
+            size       : e.size,
+            mtime      : e.mtime,
+            mtime_nsec : e.mtime_nsec,
+        };
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  every entry of the journal at `path`, in the order they were written; a last line cut short
+//  by a crash while it was written is left out
+pub fn read(path : &Path) -> io::Result<Vec<Entry>> {
+
+    let lines = BufReader::new(File::open(path)?).lines().collect::<io::Result<Vec<_>>>()?;
+    let mut entries = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry)                        => entries.push(entry),
+            Err(_) if i + 1 == lines.len()   => break,
+            Err(err)                         => return Err(err.into()),
+        }
+    }
+    Ok(entries)
+}
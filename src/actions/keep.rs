@@ -0,0 +1,110 @@
+use std::cmp::Reverse;
+
+use regex::Regex;
+
+use crate::walk::FileEntry;
+
+//-------------------------------------------------------------------------------------------------
+//  which member of a group survives when the others are acted on; ties go to the member listed
+//  first
+#[derive(Debug, Clone)]
+pub enum KeepRule {
+    Newest,
+    Oldest,
+    ShortestPath,
+    LongestPath,
+    FirstRoot,
+    PathMatches(Regex),
+}
+
+impl KeepRule {
+    pub const NAMES : [&'static str; 6] = ["newest", "oldest", "shortest-path", "longest-path", "first-root", "path-matches=<regex>"];
+
+    pub fn name(&self) -> String {
+        match self {
+            KeepRule::Newest          => "newest".to_string(),
+            KeepRule::Oldest          => "oldest".to_string(),
+            KeepRule::ShortestPath    => "shortest-path".to_string(),
+            KeepRule::LongestPath     => "longest-path".to_string(),
+            KeepRule::FirstRoot       => "first-root".to_string(),
+            KeepRule::PathMatches(re) => format!("path-matches={}", re.as_str()),
+        }
+    }
+
+    pub fn from_name(name : &str) -> Result<KeepRule, String> {
+        match name {
+            "newest"        => Ok(KeepRule::Newest),
+            "oldest"        => Ok(KeepRule::Oldest),
+            "shortest-path" => Ok(KeepRule::ShortestPath),
+            "longest-path"  => Ok(KeepRule::LongestPath),
+            "first-root"    => Ok(KeepRule::FirstRoot),
+            _               => match name.strip_prefix("path-matches=") {
+                Some(pattern) => Regex::new(pattern).map(KeepRule::PathMatches).map_err(|err| err.to_string()),
+                None          => Err(format!("expected one of {}", KeepRule::NAMES.join(", "))),
+            },
+        }
+    }
+
+    //  index of the member to keep, none if no member matches the pattern
+    pub fn choose(&self, members : &[&FileEntry]) -> Option<usize> {
+        let mut indexed = members.iter().enumerate();
+        let path_len = |e : &FileEntry| e.path.as_os_str().len();
+
+        match self {
+            KeepRule::Newest          => indexed.min_by_key(|(_, e)| Reverse((e.mtime, e.mtime_nsec))),
+            KeepRule::Oldest          => indexed.min_by_key(|(_, e)| (e.mtime, e.mtime_nsec)),
+            KeepRule::ShortestPath    => indexed.min_by_key(|(_, e)| path_len(e)),
+            KeepRule::LongestPath     => indexed.min_by_key(|(_, e)| Reverse(path_len(e))),
+            KeepRule::FirstRoot       => indexed.min_by_key(|(_, e)| e.root),
+            KeepRule::PathMatches(re) => indexed.find(|(_, e)| re.is_match(&e.path.to_string_lossy())),
+        }
+        .map(|(i, _)| i)
+    }
+
+    //  which members stay: every file under a reference root if there is one, otherwise the one
+    //  the rule picks
+    pub fn keep_flags(&self, members : &[&FileEntry]) -> Option<Vec<bool>> {
+        if members.iter().any(|e| e.reference) {
+            return Some(members.iter().map(|e| e.reference).collect());
+        }
+        let keep = self.choose(members)?;
+        Some((0..members.len()).map(|i| i == keep).collect())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn member(path : &str, mtime : i64, reference : bool) -> FileEntry {
+        FileEntry { path : PathBuf::from(path), size : 1, mtime, mtime_nsec : 0, dev : 1, ino : 0, root : 0, reference }
+    }
+
+    #[test]
+    fn the_rule_keeps_one_member() {
+        let a = member("/data/a", 1, false);
+        let b = member("/data/b", 2, false);
+
+        assert_eq!(KeepRule::Newest.keep_flags(&[&a, &b]), Some(vec![false, true]));
+        assert_eq!(KeepRule::Oldest.keep_flags(&[&a, &b]), Some(vec![true, false]));
+    }
+
+    #[test]
+    fn every_reference_member_is_kept() {
+        let a = member("/data/a", 3, false);
+        let r1 = member("/ref/a", 1, true);
+        let r2 = member("/ref/b", 2, true);
+
+        //  whatever the rule would pick, the reference files stay and only they do
+        assert_eq!(KeepRule::Newest.keep_flags(&[&a, &r1, &r2]), Some(vec![false, true, true]));
+        assert_eq!(KeepRule::from_name("path-matches=nothing").unwrap().keep_flags(&[&a, &r1]), Some(vec![false, true]));
+    }
+
+    #[test]
+    fn no_match_keeps_nothing() {
+        let a = member("/data/a", 1, false);
+        assert_eq!(KeepRule::from_name("path-matches=nothing").unwrap().keep_flags(&[&a]), None);
+    }
+}
@@ -0,0 +1,442 @@
+pub mod journal;
+pub mod keep;
+pub mod quarantine;
+pub mod script;
+pub mod undo;
+#[cfg(target_os = "linux")]
+mod reflink;
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+use self::journal::{Journal, Step};
+use self::quarantine::Manifest;
+use crate::output::text::to_mb;
+use crate::output::Report;
+use crate::pipeline::Verify;
+use crate::walk::FileEntry;
+use crate::GroupingMode;
+
+//-------------------------------------------------------------------------------------------------
+//  what is done to the members of a group that are not kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum Method {
+    Delete,
+    Hardlink,
+    Symlink(LinkKind),
+    Reflink,
+    Quarantine(PathBuf),
+}
+
+//  how a symbolic link names the kept file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkKind {
+    Relative,
+    Absolute,
+}
+
+impl LinkKind {
+    pub fn from_name(name : &str) -> Option<LinkKind> {
+        match name {
+            "relative" => Some(LinkKind::Relative),
+            "absolute" => Some(LinkKind::Absolute),
+            _          => None,
+        }
+    }
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Delete        => "delete",
+            Method::Hardlink      => "hardlink",
+            Method::Symlink(_)    => "symlink",
+            Method::Reflink       => "reflink",
+            Method::Quarantine(_) => "quarantine",
+        }
+    }
+
+    //  as the plan and the log print it, before and after the fact
+    pub fn verbs(&self) -> (&'static str, &'static str) {
+        match self {
+            Method::Delete        => ("remove", "removed"),
+            Method::Hardlink      => ("link", "linked"),
+            Method::Symlink(_)    => ("symlink", "symlinked"),
+            Method::Reflink       => ("reflink", "reflinked"),
+            Method::Quarantine(_) => ("move", "moved"),
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  one duplicate group split into the files that stay and the files to act on, or the reason
+//  it is left alone
+pub struct GroupPlan<'a> {
+    pub key     : &'a str,
+    pub size    : u64,
+    pub keep    : Vec<&'a FileEntry>,
+    pub act_on  : Vec<&'a FileEntry>,
+    pub skipped : Option<String>,
+}
+
+//  how a run went, `failed` counts files that were meant to be acted on and were not
+#[derive(Default)]
+pub struct Outcome {
+    pub files   : usize,
+    pub bytes   : u64,
+    pub skipped : usize,
+    pub failed  : usize,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  only groups whose members were compared byte by byte are ever acted on, everything else is
+//  planned as skipped
+pub fn plan<'r>(report : &'r Report<'_>) -> Vec<GroupPlan<'r>> {
+
+    let config = report.config;
+    let confirmed = config.verify == Verify::Bytes && config.mode == GroupingMode::Content;
+
+    report.duplicate_groups()
+        .map(|(key, _, val)| {
+            let mut group = GroupPlan { key, size : val[0].size, keep : vec![], act_on : vec![], skipped : None };
+
+            if !confirmed {
+                group.skipped = Some("not confirmed by comparing contents".to_string());
+                return group;
+            }
+
+            match config.keep.keep_flags(val) {
+                Some(flags) => for (e, keep) in val.iter().zip(flags) {
+                    if keep {group.keep.push(e)} else {group.act_on.push(e)}
+                },
+                None        => group.skipped = Some(format!("no member to keep by --keep {}", config.keep.name())),
+            }
+            group
+        })
+        .collect()
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the file as it is now, provided it is still the one the scan looked at
+fn unchanged(e : &FileEntry) -> io::Result<fs::Metadata> {
+    let md = fs::symlink_metadata(&e.path)?;
+    let now = FileEntry::new(e.path.clone(), &md, e.root, e.reference);
+
+    if !md.is_file() || (now.dev, now.ino, now.size, now.mtime, now.mtime_nsec) != (e.dev, e.ino, e.size, e.mtime, e.mtime_nsec) {
+        return Err(io::Error::other("changed since the scan"));
+    }
+    Ok(md)
+}
+
+#[cfg(unix)]
+fn link_count(md : &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    md.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_md : &fs::Metadata) -> u64 {
+    1
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the kept file `e` is replaced with, or why it is left as it is; a member with the inode of a
+//  kept file is that file under another name, a hard link or the same directory reached through
+//  two roots, and acting on it would lose the kept copy
+fn source_for<'a>(method : &Method, keep : &[&'a FileEntry], e : &FileEntry) -> Result<&'a FileEntry, String> {
+    if let Some(same) = keep.iter().find(|k| k.ino != 0 && (k.dev, k.ino) == (e.dev, e.ino)) {
+        return Err(format!("the same file as {}", same.path.to_string_lossy()));
+    }
+    match method {
+        Method::Delete | Method::Symlink(_) => Ok(keep[0]),
+        //  a quarantine under a scanned root has its files scanned again by later runs
+        Method::Quarantine(dir)             => {
+            if journal::absolute_path(&e.path).starts_with(journal::absolute_path(dir)) {
+                return Err("already in the quarantine".to_string());
+            }
+            Ok(keep[0])
+        }
+        Method::Hardlink | Method::Reflink  => {
+            let same_dev = keep.iter().find(|k| k.dev == e.dev).copied();
+            match method {
+                //  subvolumes of one filesystem share extents across devices
+                Method::Reflink => Ok(same_dev.unwrap_or(keep[0])),
+                _               => same_dev.ok_or_else(|| format!("on another device than {}", keep[0].path.to_string_lossy())),
+            }
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  a name next to `path` for building its replacement, hidden and unlikely to be taken
+fn temp_path(path : &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.lsdups-{}", name, process::id()))
+}
+
+//  creates the replacement under a temporary name and renames it over `path`, so `path` is
+//  either the old file or the new one and never missing
+fn replace_via_temp<F>(path : &Path, create : F) -> io::Result<()>
+where
+    F : FnOnce(&Path) -> io::Result<()>
+{
+    let tmp = temp_path(path);
+    create(&tmp)?;
+    if let Err(err) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(err);
+    }
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the path to `target` as seen from the directory `from`, both absolute and free of symlinks
+fn relative_path(from : &Path, target : &Path) -> PathBuf {
+    let from : Vec<Component> = from.components().collect();
+    let to : Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    iter::repeat_n(Component::ParentDir, from.len() - common)
+        .chain(to[common..].iter().copied())
+        .collect()
+}
+
+//  what a symlink at `e` has to point to to reach `source`
+fn link_target(kind : LinkKind, source : &FileEntry, e : &FileEntry) -> io::Result<PathBuf> {
+    let source = fs::canonicalize(&source.path)?;
+    match kind {
+        LinkKind::Absolute => Ok(source),
+        LinkKind::Relative => {
+            let dir = e.path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+            Ok(relative_path(&fs::canonicalize(dir)?, &source))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+use self::reflink::reflink;
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_kept : &Path, _path : &Path, _size : u64) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are only made on linux"))
+}
+
+#[cfg(unix)]
+fn symlink(target : &Path, path : &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target : &Path, _path : &Path) -> io::Result<()> {
+    Err(io::Error::other("symbolic links are only made on unix"))
+}
+
+//-------------------------------------------------------------------------------------------------
+//  what a new link points to, or where the file is moved to, worked out before anything is
+//  touched so the journal can name it
+fn target_for(method : &Method, source : &FileEntry, e : &FileEntry) -> io::Result<Option<PathBuf>> {
+    match method {
+        Method::Symlink(kind)   => link_target(*kind, source, e).map(Some),
+        Method::Quarantine(dir) => Ok(Some(quarantine::destination(dir, &e.path))),
+        _                       => Ok(None),
+    }
+}
+
+//  acts on one file as it was found in `md`, returning the bytes it frees; a file with other
+//  hard links frees nothing unless its extents are shared, which all its links see
+fn apply(method : &Method, source : &FileEntry, e : &FileEntry, md : &fs::Metadata, target : Option<&Path>) -> io::Result<u64> {
+    let freed = if link_count(md) == 1 || *method == Method::Reflink {e.size} else {0};
+    let target = || target.ok_or_else(|| io::Error::other("no target worked out"));
+
+    match method {
+        Method::Delete        => fs::remove_file(&e.path)?,
+        Method::Hardlink      => replace_via_temp(&e.path, |tmp| fs::hard_link(&source.path, tmp))?,
+        Method::Symlink(_)    => {
+            //  a link over the kept file itself would point at nothing but itself
+            if fs::canonicalize(&e.path)? == fs::canonicalize(&source.path)? {
+                return Err(io::Error::other("the same file as the kept one"));
+            }
+            replace_via_temp(&e.path, |tmp| symlink(target()?, tmp))?
+        }
+        Method::Reflink       => reflink(&source.path, &e.path, e.size)?,
+        Method::Quarantine(_) => quarantine::move_file(&e.path, target()?)?,
+    }
+    Ok(freed)
+}
+
+//  checks the file, writes down what is about to happen, does it and writes down that it did;
+//  the outer error is the journal failing, the inner one the file
+fn act(method : &Method, source : &FileEntry, e : &FileEntry, key : &str, journal : &mut Journal) -> io::Result<io::Result<(u64, Option<PathBuf>)>> {
+    let (md, target) = match unchanged(e).and_then(|md| Ok((md, target_for(method, source, e)?))) {
+        Ok(planned) => planned,
+        Err(err)    => return Ok(Err(err)),
+    };
+
+    journal.record(Step::Intent, method.name(), e, source, target.as_deref(), key)?;
+    let freed = match apply(method, source, e, &md, target.as_deref()) {
+        Ok(freed) => freed,
+        Err(err)  => return Ok(Err(err)),
+    };
+    journal.record(Step::Done, method.name(), e, source, target.as_deref(), key)?;
+
+    Ok(Ok((freed, target)))
+}
+
+//-------------------------------------------------------------------------------------------------
+//  prints what `method` would do to every group, and does it when `execute` is set; groups
+//  whose kept files changed since the scan are left alone. The preview counts every inode
+//  once, links to it outside the scanned files are only noticed when executing. Every change is
+//  recorded in `journal` before and after it is made, which is only needed when executing; a
+//  journal that cannot be written to stops the run. Quarantined files are also listed in the
+//  manifest of the quarantine
+pub fn run(report : &Report, method : &Method, journal : Option<&mut Journal>, out : &mut dyn Write) -> io::Result<Outcome> {
+    run_plan(report, plan(report), method, journal, out)
+}
+
+//  the same for groups split by hand instead of by --keep
+pub fn run_plan(report : &Report, plans : Vec<GroupPlan>, method : &Method, mut journal : Option<&mut Journal>, out : &mut dyn Write) -> io::Result<Outcome> {
+
+    let execute = journal.is_some();
+    let mut manifest = match method {
+        Method::Quarantine(dir) if execute => Some(Manifest::open(dir)?),
+        _                                  => None,
+    };
+
+    let (verb, done) = method.verbs();
+    let mut outcome = Outcome::default();
+    let mut counted = HashSet::new();
+
+    for group in plans {
+        writeln!(out, "\n{} * {}, {:.3} MB each", group.key, group.keep.len() + group.act_on.len(), to_mb(group.size))?;
+        writeln!(out, "----------------------------------------")?;
+
+        if let Some(reason) = &group.skipped {
+            writeln!(out, "skipped: {}", reason)?;
+            continue;
+        }
+
+        for e in &group.keep {
+            writeln!(out, "{:<10}{}", "keep", e.path.to_string_lossy())?;
+        }
+
+        if execute {
+            if let Some(err) = group.keep.iter().find_map(|e| unchanged(e).err()) {
+                writeln!(out, "skipped: kept copy {}", err)?;
+                outcome.failed += group.act_on.len();
+                continue;
+            }
+        }
+
+        for e in &group.act_on {
+            let source = match source_for(method, &group.keep, e) {
+                Ok(source) => source,
+                Err(why)   => {
+                    writeln!(out, "{:<10}{}: {}", "skipped", e.path.to_string_lossy(), why)?;
+                    outcome.skipped += 1;
+                    continue;
+                }
+            };
+
+            let journal = match journal.as_deref_mut() {
+                Some(journal) => journal,
+                None          => {
+                    writeln!(out, "{:<10}{}", verb, e.path.to_string_lossy())?;
+                    outcome.files += 1;
+                    if counted.insert((e.dev, e.ino)) {
+                        outcome.bytes += e.size;
+                    }
+                    continue;
+                }
+            };
+
+            match act(method, source, e, group.key, journal)? {
+                Ok((freed, target)) => {
+                    //  only confirmed groups get here, their key is the hash of the whole file
+                    if let (Some(manifest), Some(target)) = (manifest.as_mut(), &target) {
+                        manifest.record(e, target, source, report.config.algorithm, group.key)?;
+                    }
+                    writeln!(out, "{:<10}{}", done, e.path.to_string_lossy())?;
+                    outcome.files += 1;
+                    outcome.bytes += freed;
+                }
+                //  the filesystem could not do it, nothing went wrong
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                    writeln!(out, "{:<10}{}: {}", "skipped", e.path.to_string_lossy(), err)?;
+                    outcome.skipped += 1;
+                }
+                Err(err)            => {
+                    writeln!(out, "{:<10}{}: {}", "failed", e.path.to_string_lossy(), err)?;
+                    outcome.failed += 1;
+                }
+            }
+        }
+    }
+
+    writeln!(out)?;
+    if execute {
+        writeln!(out, "{} {} files, reclaimed {:.3} MB, {} skipped, {} failed",
+                    done, outcome.files, to_mb(outcome.bytes), outcome.skipped, outcome.failed)?;
+    }
+    else {
+        writeln!(out, "dry run, would {} {} files ({:.3} MB), {} skipped; add --execute to do it",
+                    verb, outcome.files, to_mb(outcome.bytes), outcome.skipped)?;
+    }
+    out.flush()?;
+
+    Ok(outcome)
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path : &Path) -> FileEntry {
+        FileEntry::new(path.to_path_buf(), &fs::metadata(path).unwrap(), 0, false)
+    }
+
+    #[test]
+    fn relative_path_climbs_to_the_common_directory() {
+        assert_eq!(relative_path(Path::new("/data/b"), Path::new("/data/a/x.bin")), PathBuf::from("../a/x.bin"));
+        assert_eq!(relative_path(Path::new("/data/b/c"), Path::new("/data/a/x.bin")), PathBuf::from("../../a/x.bin"));
+        assert_eq!(relative_path(Path::new("/data"), Path::new("/data/a/x.bin")), PathBuf::from("a/x.bin"));
+        assert_eq!(relative_path(Path::new("/data/a"), Path::new("/data/a/x.bin")), PathBuf::from("x.bin"));
+        assert_eq!(relative_path(Path::new("/srv"), Path::new("/data/x.bin")), PathBuf::from("../data/x.bin"));
+    }
+
+    //  run_plan reports Unsupported as skipped, the file must be left alone
+    #[test]
+    fn reflink_on_tmpfs_is_unsupported() {
+        let shm = Path::new("/dev/shm");
+        if !shm.is_dir() {
+            return;
+        }
+        let dir = tempfile::tempdir_in(shm).unwrap();
+        fs::write(dir.path().join("kept"), b"same contents").unwrap();
+        fs::write(dir.path().join("copy"), b"same contents").unwrap();
+        let kept = entry(&dir.path().join("kept"));
+        let copy = entry(&dir.path().join("copy"));
+
+        let md = fs::metadata(&copy.path).unwrap();
+        let err = apply(&Method::Reflink, &kept, &copy, &md, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(fs::read(&copy.path).unwrap(), b"same contents");
+    }
+
+    #[test]
+    fn symlink_never_replaces_the_kept_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("kept"), b"same contents").unwrap();
+        let kept = entry(&dir.path().join("kept"));
+        let again = entry(&dir.path().join(".").join("kept"));
+
+        let md = fs::metadata(&again.path).unwrap();
+        let target = link_target(LinkKind::Absolute, &kept, &again).unwrap();
+        assert!(apply(&Method::Symlink(LinkKind::Absolute), &kept, &again, &md, Some(&target)).is_err());
+        assert!(fs::symlink_metadata(&kept.path).unwrap().is_file());
+    }
+}
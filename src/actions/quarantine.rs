@@ -0,0 +1,147 @@
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::journal::{absolute_path, now};
+use crate::hashing::{self, Algorithm};
+use crate::output::json::raw_path;
+use crate::output::utc_timestamp;
+use crate::walk::FileEntry;
+
+pub const MANIFEST_NAME : &str = "manifest.ndjson";
+
+//-------------------------------------------------------------------------------------------------
+//  one quarantined file, `hash` is the digest of its contents by `algorithm` as the scan found it
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    time        : String,
+    #[serde(with = "raw_path")]
+    original    : PathBuf,
+    #[serde(with = "raw_path")]
+    quarantined : PathBuf,
+    #[serde(with = "raw_path")]
+    kept        : PathBuf,
+    algorithm   : String,
+    hash        : String,
+    size        : u64,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  where `path` goes inside the quarantine directory `dir`, the absolute path below it
+pub fn destination(dir : &Path, path : &Path) -> PathBuf {
+    absolute_path(path).components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .fold(absolute_path(dir), |acc, c| acc.join(c))
+}
+
+//-------------------------------------------------------------------------------------------------
+//  renames where it can, otherwise copies with the timestamps and removes the original; never
+//  replaces a file already at `to`
+pub fn move_file(from : &Path, to : &Path) -> io::Result<()> {
+
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", to.to_string_lossy())));
+    }
+
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            let md = fs::metadata(from)?;
+            fs::copy(from, to)?;
+            let copy = OpenOptions::new().write(true).open(to)?;
+            copy.set_times(FileTimes::new().set_accessed(md.accessed()?).set_modified(md.modified()?))?;
+            copy.sync_all()?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the list of quarantined files kept in the quarantine directory, appended to by every run
+//  that moves files there
+pub struct Manifest {
+    file : File,
+}
+
+impl Manifest {
+    pub fn open(dir : &Path) -> io::Result<Manifest> {
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(dir.join(MANIFEST_NAME))?;
+        Ok(Manifest { file })
+    }
+
+    pub fn record(&mut self, e : &FileEntry, quarantined : &Path, kept : &FileEntry, algorithm : Algorithm, hash : &str) -> io::Result<()> {
+        let entry = Entry {
+            time        : utc_timestamp(now()),
+            original    : absolute_path(&e.path),
+            quarantined : absolute_path(quarantined),
+            kept        : absolute_path(&kept.path),
+            algorithm   : algorithm.name().to_string(),
+            hash        : hash.to_string(),
+            size        : e.size,
+        };
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  puts one quarantined file back, provided it still hashes to what the manifest says and
+//  nothing took its place in the meantime
+fn restore_entry(entry : &Entry) -> io::Result<()> {
+
+    let algorithm = Algorithm::from_name(&entry.algorithm)
+                        .ok_or_else(|| io::Error::other(format!("unknown hash algorithm {}", entry.algorithm)))?;
+
+    if hashing::to_hex(&hashing::hash_file(&entry.quarantined, algorithm)?) != entry.hash {
+        return Err(io::Error::other("contents no longer match the manifest"));
+    }
+
+    move_file(&entry.quarantined, &entry.original)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  restores every file listed in `manifest`, latest first so a path quarantined twice ends up
+//  with the version it had before the first run; returns how many were restored and how many
+//  could not be
+pub fn restore(manifest : &Path, out : &mut dyn Write) -> io::Result<(usize, usize)> {
+
+    let mut entries = vec![];
+    for line in BufReader::new(File::open(manifest)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str::<Entry>(&line)?);
+    }
+
+    let mut restored = 0;
+    let mut failed = 0;
+
+    for entry in entries.iter().rev() {
+        match restore_entry(entry) {
+            Ok(())   => {
+                writeln!(out, "{:<10}{}", "restored", entry.original.to_string_lossy())?;
+                restored += 1;
+            }
+            Err(err) => {
+                writeln!(out, "{:<10}{}: {}", "failed", entry.original.to_string_lossy(), err)?;
+                failed += 1;
+            }
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "restored {} files, {} failed", restored, failed)?;
+    out.flush()?;
+
+    Ok((restored, failed))
+}
@@ -0,0 +1,153 @@
+use std::fs::{File, FileTimes, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+//  _IOWR(0x94, 54, struct file_dedupe_range), not in libc yet
+const FIDEDUPERANGE : libc::Ioctl = 0xC018_9436u32 as libc::Ioctl;
+
+const FILE_DEDUPE_RANGE_DIFFERS : i32 = 1;
+
+//  kernels cap how much one call dedupes, bigger files take several
+const DEDUPE_CHUNK : u64 = 16 * 1024 * 1024;
+
+//-------------------------------------------------------------------------------------------------
+//  struct file_dedupe_range with room for a single destination
+#[repr(C)]
+struct DedupeRange {
+    src_offset : u64,
+    src_length : u64,
+    dest_count : u16,
+    reserved1  : u16,
+    reserved2  : u32,
+    info       : DedupeInfo,
+}
+
+#[repr(C)]
+struct DedupeInfo {
+    dest_fd       : i64,
+    dest_offset   : u64,
+    bytes_deduped : u64,
+    status        : i32,
+    reserved      : u32,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  errors that mean the filesystem cannot do it, as opposed to something going wrong
+fn unsupported(err : io::Error) -> io::Error {
+    let reason = match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) | Some(libc::EINVAL) => "the filesystem does not share extents",
+        Some(libc::EXDEV)                                              => "on another filesystem than the kept file",
+        _                                                              => return err,
+    };
+    io::Error::new(io::ErrorKind::Unsupported, reason)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  lets the kernel compare both files and share the extents of `dest` with `src` where they are
+//  the same, leaving `dest` untouched otherwise
+fn dedupe(src : &File, dest : &File, size : u64) -> io::Result<()> {
+
+    let mut offset = 0;
+
+    while offset < size {
+        let mut range = DedupeRange {
+            src_offset : offset,
+            src_length : (size - offset).min(DEDUPE_CHUNK),
+            dest_count : 1,
+            reserved1  : 0,
+            reserved2  : 0,
+            info       : DedupeInfo {
+                dest_fd       : dest.as_raw_fd() as i64,
+                dest_offset   : offset,
+                bytes_deduped : 0,
+                status        : 0,
+                reserved      : 0,
+            },
+        };
+
+        // SAFETY: `range` is a file_dedupe_range followed by exactly `dest_count` infos and
+        // outlives the call, both descriptors are open
+        if unsafe { libc::ioctl(src.as_raw_fd(), FIDEDUPERANGE, &mut range as *mut DedupeRange) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        match range.info.status {
+            status if status < 0                => return Err(io::Error::from_raw_os_error(-status)),
+            FILE_DEDUPE_RANGE_DIFFERS           => return Err(io::Error::other("contents differ from the kept file")),
+            _ if range.info.bytes_deduped == 0  => return Err(io::Error::other("the kernel deduplicated nothing")),
+            _                                   => offset += range.info.bytes_deduped,
+        }
+    }
+
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+//  replaces the contents of `dest` with a clone of `src` and puts its timestamps back; only
+//  used where dedupe is not available, the scan already compared the contents
+fn clone(src : &File, dest : &File) -> io::Result<()> {
+
+    let md = dest.metadata()?;
+
+    // SAFETY: FICLONE takes the source descriptor as its argument, both are open
+    if unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    dest.set_times(FileTimes::new().set_accessed(md.accessed()?).set_modified(md.modified()?))
+}
+
+//-------------------------------------------------------------------------------------------------
+//  shares the extents of `path` with those of `kept`; the file keeps its inode, owner and
+//  timestamps. Errors of kind Unsupported say why the filesystem could not do it
+pub fn reflink(kept : &Path, path : &Path, size : u64) -> io::Result<()> {
+
+    let src = File::open(kept)?;
+    let dest = OpenOptions::new().read(true).write(true).open(path)
+                .or_else(|_| File::open(path))?;
+
+    match dedupe(&src, &dest, size) {
+        Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) || err.raw_os_error() == Some(libc::EINVAL) => {
+            clone(&src, &dest).map_err(unsupported)
+        }
+        result => result.map_err(unsupported),
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn filesystem_errors_are_unsupported() {
+        for errno in &[libc::EOPNOTSUPP, libc::ENOTTY, libc::EINVAL, libc::EXDEV] {
+            assert_eq!(unsupported(io::Error::from_raw_os_error(*errno)).kind(), io::ErrorKind::Unsupported);
+        }
+        assert_eq!(unsupported(io::Error::from_raw_os_error(libc::EIO)).raw_os_error(), Some(libc::EIO));
+    }
+
+    //  tmpfs neither dedupes nor clones, the file has to be left as it was
+    #[test]
+    fn tmpfs_falls_back_to_unsupported() {
+        let shm = Path::new("/dev/shm");
+        if !shm.is_dir() {
+            return;
+        }
+        let dir = tempfile::tempdir_in(shm).unwrap();
+        let kept = dir.path().join("kept");
+        let path = dir.path().join("copy");
+        fs::write(&kept, b"same contents").unwrap();
+        fs::write(&path, b"same contents").unwrap();
+        let before = fs::metadata(&path).unwrap();
+
+        let err = reflink(&kept, &path, before.len()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let after = fs::metadata(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"same contents");
+        assert_eq!(after.modified().unwrap(), before.modified().unwrap());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::journal::{absolute_path, now};
+use super::{link_target, plan, quarantine, source_for, GroupPlan, Method, Outcome};
+use crate::output::text::to_mb;
+use crate::output::{path_bytes, utc_timestamp, Report};
+use crate::walk::FileEntry;
+
+//  `stat` is not in POSIX, GNU and BSD spell it differently
+const GUARD : &str = r#"set -eu
+
+meta() {
+    stat -c '%s %Y' -- "$1" 2>/dev/null || stat -f '%z %m' -- "$1" 2>/dev/null || true
+}
+
+changed=0
+check() {
+    if [ "$(meta "$1")" != "$2 $3" ]; then
+        printf 'changed since the scan: %s\n' "$1" >&2
+        changed=1
+    fi
+}
+absent() {
+    if [ -e "$1" ] || [ -L "$1" ]; then
+        printf 'already there: %s\n' "$1" >&2
+        changed=1
+    fi
+}
+"#;
+
+//-------------------------------------------------------------------------------------------------
+//  a path as one single-quoted shell word, quotes in it are closed, escaped and opened again
+fn word(path : &Path) -> Vec<u8> {
+    let mut word = vec![b'\''];
+    for &b in path_bytes(path).iter() {
+        match b {
+            b'\'' => word.extend_from_slice(b"'\\''"),
+            b     => word.push(b),
+        }
+    }
+    word.push(b'\'');
+    word
+}
+
+fn quote(path : &Path) -> Vec<u8> {
+    word(&absolute_path(path))
+}
+
+//  one line of shell, `words` are written as they are
+fn command(out : &mut Vec<u8>, words : &[&[u8]]) {
+    out.extend_from_slice(&words.join(&b' '));
+    out.push(b'\n');
+}
+
+//  a guard line making sure the file is still as the scan saw it
+fn check(out : &mut Vec<u8>, e : &FileEntry) {
+    command(out, &[b"check", &quote(&e.path), e.size.to_string().as_bytes(), e.mtime.to_string().as_bytes()]);
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the commands acting on `e`, or why there are none
+fn commands(method : &Method, source : &FileEntry, e : &FileEntry, guard : &mut Vec<u8>, body : &mut Vec<u8>) -> io::Result<()> {
+    let path = quote(&e.path);
+    match method {
+        Method::Delete          => command(body, &[b"rm -f --", &path]),
+        Method::Hardlink        => command(body, &[b"ln -f --", &quote(&source.path), &path]),
+        Method::Symlink(kind)   => {
+            //  quoted as it is, a relative target must not be made absolute
+            let target = link_target(*kind, source, e)?;
+            command(body, &[b"ln -sf --", &word(&target), &path]);
+        }
+        Method::Quarantine(dir) => {
+            let dest = quarantine::destination(dir, &e.path);
+            let dest_dir = dest.parent().unwrap_or(dir);
+            command(guard, &[b"absent", &quote(&dest)]);
+            command(body, &[b"mkdir -p --", &quote(dest_dir)]);
+            command(body, &[b"mv --", &path, &quote(&dest)]);
+        }
+        Method::Reflink         => return Err(io::Error::new(io::ErrorKind::Unsupported, "no shell command shares extents")),
+    }
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+//  writes a POSIX shell script doing what `method` would do to every group, to be read before it
+//  is run; it does nothing at all unless every file it names still has the size and mtime the
+//  scan saw. Paths are written absolute, as their raw bytes in single quotes
+pub fn write(report : &Report, method : &Method, out : &mut dyn Write) -> io::Result<Outcome> {
+    let kept_by = format!("--keep {}", report.config.keep.name());
+    write_plan(report, plan(report), method, &kept_by, out)
+}
+
+//  the same for groups split some other way, `kept_by` says which for the header
+pub fn write_plan(report : &Report, plans : Vec<GroupPlan>, method : &Method, kept_by : &str, out : &mut dyn Write) -> io::Result<Outcome> {
+
+    let (_, done) = method.verbs();
+    let mut outcome = Outcome::default();
+    let mut counted = HashSet::new();
+
+    let mut guard = vec![];
+    let mut body = vec![];
+
+    for group in plans {
+        let count = group.keep.len() + group.act_on.len();
+        writeln!(body, "\n# {} * {}, {} bytes each, {:.3} MB wasted",
+                    group.key, count, group.size, to_mb(group.size * (count as u64 - 1)))?;
+
+        if let Some(reason) = &group.skipped {
+            writeln!(body, "# skipped: {}", reason)?;
+            continue;
+        }
+
+        for e in &group.keep {
+            writeln!(body, "# keep {:?}", absolute_path(&e.path))?;
+            check(&mut guard, e);
+        }
+
+        for e in &group.act_on {
+            let planned = source_for(method, &group.keep, e)
+                            .map_err(io::Error::other)
+                            .and_then(|source| {
+                                let mut lines = vec![];
+                                commands(method, source, e, &mut guard, &mut lines).map(|_| lines)
+                            });
+            match planned {
+                Ok(lines) => {
+                    check(&mut guard, e);
+                    body.extend_from_slice(&lines);
+                    outcome.files += 1;
+                    if counted.insert((e.dev, e.ino)) {
+                        outcome.bytes += e.size;
+                    }
+                }
+                Err(err)  => {
+                    writeln!(body, "# skipped {:?}: {}", absolute_path(&e.path), err)?;
+                    outcome.skipped += 1;
+                }
+            }
+        }
+    }
+
+    writeln!(out, "#!/bin/sh")?;
+    writeln!(out, "#")?;
+    writeln!(out, "# written by lsdups-rust at {} UTC", utc_timestamp(now()))?;
+    for root in report.roots {
+        writeln!(out, "# {} {:?}", if root.reference {"reference"} else {"root"}, absolute_path(&root.path))?;
+    }
+    writeln!(out, "# --{}, kept by {}: {} files, {:.3} MB, {} skipped",
+                method.name(), kept_by, outcome.files, to_mb(outcome.bytes), outcome.skipped)?;
+    writeln!(out, "#")?;
+    writeln!(out, "# Read every command before running this. Nothing is done unless every file named")?;
+    writeln!(out, "# below still has the size and modification time the scan saw.")?;
+    writeln!(out)?;
+
+    out.write_all(GUARD.as_bytes())?;
+    writeln!(out)?;
+    out.write_all(&guard)?;
+    writeln!(out)?;
+    writeln!(out, "if [ \"$changed\" -ne 0 ]; then")?;
+    writeln!(out, "    echo 'nothing done, scan again' >&2")?;
+    writeln!(out, "    exit 1")?;
+    writeln!(out, "fi")?;
+    out.write_all(&body)?;
+    writeln!(out)?;
+    writeln!(out, "echo '{} {} files'", done, outcome.files)?;
+    out.flush()?;
+
+    Ok(outcome)
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    fn entry(path : &str, ino : u64, size : u64, mtime : i64) -> FileEntry {
+        FileEntry { path : PathBuf::from(path), size, mtime, mtime_nsec : 0, dev : 1, ino, root : 0, reference : false }
+    }
+
+    #[test]
+    fn quotes_are_closed_escaped_and_opened_again() {
+        assert_eq!(word(Path::new("/data/a b")), b"'/data/a b'");
+        assert_eq!(word(Path::new("/data/it's")), br"'/data/it'\''s'");
+        assert_eq!(word(Path::new("/data/$(rm -rf ~)`x`")), b"'/data/$(rm -rf ~)`x`'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_are_written_as_raw_bytes() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(word(Path::new(OsStr::from_bytes(b"/z\xff'\n"))), b"'/z\xff'\\''\n'");
+    }
+
+    #[test]
+    fn delete_is_one_rm_of_the_quoted_path() {
+        let (mut guard, mut body) = (vec![], vec![]);
+        commands(&Method::Delete, &entry("/k", 1, 1, 0), &entry("/d/it's", 2, 1, 0), &mut guard, &mut body).unwrap();
+        assert_eq!(body, b"rm -f -- '/d/it'\\''s'\n");
+        assert!(guard.is_empty());
+    }
+
+    //  what the guard makes of `path` when the scan saw it with `size` and `mtime`
+    #[cfg(unix)]
+    fn guard(dir : &Path, path : &Path, size : u64, mtime : i64) -> String {
+        let mut script = GUARD.as_bytes().to_vec();
+        check(&mut script, &FileEntry { path : path.to_path_buf(), ..entry("", 1, size, mtime) });
+        script.extend_from_slice(b"echo \"changed=$changed\"\n");
+
+        let file = dir.join("guard.sh");
+        fs::write(&file, script).unwrap();
+        let output = Command::new("sh").arg(&file).current_dir(dir).output().unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_guard_compares_size_and_mtime() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("it's $(touch pwned) `touch pwned`");
+        fs::write(&path, b"contents").unwrap();
+        let md = fs::metadata(&path).unwrap();
+
+        assert_eq!(guard(dir.path(), &path, md.len(), md.mtime()), "changed=0\n");
+        assert_eq!(guard(dir.path(), &path, md.len() + 1, md.mtime()), "changed=1\n");
+        assert_eq!(guard(dir.path(), &path, md.len(), md.mtime() - 1), "changed=1\n");
+        assert_eq!(guard(dir.path(), &dir.path().join("gone"), md.len(), md.mtime()), "changed=1\n");
+        assert!(!dir.path().join("pwned").exists());
+    }
+}
@@ -0,0 +1,262 @@
+use std::fs::{self, FileTimes, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::journal::{self, Entry, Step};
+use super::{quarantine, replace_via_temp};
+use crate::hashing::{self, Algorithm};
+use crate::walk::FileEntry;
+
+//-------------------------------------------------------------------------------------------------
+//  what undoing one entry came to, files left as they are say why
+enum Undone {
+    Reversed,
+    Untouched(&'static str),
+}
+
+//-------------------------------------------------------------------------------------------------
+//  whether `md` is still the file the entry describes as it was before the run
+fn is_original(entry : &Entry, md : &fs::Metadata) -> bool {
+    let now = FileEntry::new(entry.path.clone(), md, 0, false);
+    md.is_file() && (now.size, now.mtime, now.mtime_nsec) == (entry.size, entry.mtime, entry.mtime_nsec)
+}
+
+//  whether `md` is a hard link of the kept file, never true where there are no inode numbers
+fn is_kept(entry : &Entry, md : &fs::Metadata) -> bool {
+    let kept = match fs::metadata(&entry.kept) {
+        Ok(kept) => FileEntry::new(entry.kept.clone(), &kept, 0, false),
+        Err(_)   => return false,
+    };
+    let now = FileEntry::new(entry.path.clone(), md, 0, false);
+    now.ino != 0 && (now.dev, now.ino) == (kept.dev, kept.ino)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  whether `path` still has the content the journal recorded for the kept file
+fn has_content(entry : &Entry, path : &Path) -> io::Result<()> {
+    let algorithm = Algorithm::from_name(&entry.algorithm)
+                        .ok_or_else(|| io::Error::other(format!("unknown hash algorithm {}", entry.algorithm)))?;
+
+    match hashing::hash_file(path, algorithm) {
+        Ok(digest) if hashing::to_hex(&digest) == entry.hash => Ok(()),
+        Ok(_)                                                => Err(io::Error::other(format!("{} changed since, the content is gone", path.to_string_lossy()))),
+        Err(err)                                             => Err(io::Error::other(format!("{} is gone, and with it the content: {}", path.to_string_lossy(), err))),
+    }
+}
+
+//  puts a copy of the kept file with the recorded mtime at the path, over whatever link is there
+fn copy_back(entry : &Entry) -> io::Result<Undone> {
+    let path = entry.path.as_path();
+    has_content(entry, &entry.kept)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mtime = UNIX_EPOCH + Duration::new(entry.mtime.max(0) as u64, entry.mtime_nsec);
+    replace_via_temp(path, |tmp| {
+        fs::copy(&entry.kept, tmp)?;
+        let copy = OpenOptions::new().write(true).open(tmp)?;
+        copy.set_times(FileTimes::new().set_modified(mtime))?;
+        copy.sync_all()
+    })?;
+    Ok(Undone::Reversed)
+}
+
+//  moves a quarantined file back, provided it still has the content it was moved with
+fn move_back(entry : &Entry, quarantined : &Path) -> io::Result<Undone> {
+    has_content(entry, quarantined)?;
+    quarantine::move_file(quarantined, &entry.path)?;
+    Ok(Undone::Reversed)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  reverses one intended change after looking at what is at the path now, the journal alone
+//  cannot tell whether a run cut short got to the file
+fn undo_entry(entry : &Entry) -> io::Result<Undone> {
+
+    //  shared extents leave both files with their own content
+    if entry.action == "reflink" {
+        return Ok(Undone::Untouched("reflinks leave the contents as they were"));
+    }
+
+    let path = entry.path.as_path();
+    let current = match fs::symlink_metadata(path) {
+        Ok(md)                                            => Some(md),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err)                                          => return Err(err),
+    };
+    let target = entry.target.as_deref();
+
+    if let Some(md) = &current {
+        if is_original(entry, md) && !is_kept(entry, md) {
+            return Ok(Undone::Untouched("still the file the run found"));
+        }
+    }
+
+    match (entry.action.as_str(), &current) {
+        ("delete", None)                                  => copy_back(entry),
+        ("hardlink", Some(md)) if is_kept(entry, md)      => copy_back(entry),
+        ("symlink", Some(md)) if md.file_type().is_symlink()
+                                 && fs::read_link(path).ok().as_deref() == target => copy_back(entry),
+        ("quarantine", None)                              => match target.filter(|t| t.exists()) {
+            Some(quarantined) => move_back(entry, quarantined),
+            None              => Err(io::Error::other("no longer in the quarantine")),
+        },
+        ("delete", _) | ("hardlink", _) | ("symlink", _) | ("quarantine", _) => match current {
+            Some(_) => Err(io::Error::other("another file took its place since")),
+            None    => Err(io::Error::other("removed since")),
+        },
+        (action, _)                                       => Err(io::Error::other(format!("unknown action {}", action))),
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  reverses every change in the journal at `path`, latest first; changes the run did not get to
+//  are left alone, changes that can no longer be reversed are reported as failed. Returns how
+//  many were undone, left as they were and failed
+pub fn undo(path : &Path, out : &mut dyn Write) -> io::Result<(usize, usize, usize)> {
+
+    let entries = journal::read(path)?;
+
+    let mut undone = 0;
+    let mut untouched = 0;
+    let mut failed = 0;
+
+    for entry in entries.iter().rev().filter(|e| e.step == Step::Intent) {
+        match undo_entry(entry) {
+            Ok(Undone::Reversed)       => {
+                writeln!(out, "{:<10}{}", "undone", entry.path.to_string_lossy())?;
+                undone += 1;
+            }
+            Ok(Undone::Untouched(why)) => {
+                writeln!(out, "{:<10}{}: {}", "untouched", entry.path.to_string_lossy(), why)?;
+                untouched += 1;
+            }
+            Err(err)                   => {
+                writeln!(out, "{:<10}{}: {}", "failed", entry.path.to_string_lossy(), err)?;
+                failed += 1;
+            }
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "undone {} files, {} untouched, {} cannot be undone", undone, untouched, failed)?;
+    out.flush()?;
+
+    Ok((undone, untouched, failed))
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    //  two files with the same contents, `copy` being the one acted on
+    fn setup() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("kept");
+        let copy = dir.path().join("copy");
+        fs::write(&kept, b"same contents").unwrap();
+        fs::write(&copy, b"same contents").unwrap();
+        copy_mtime(&copy);
+        (dir, kept, copy)
+    }
+
+    //  an mtime the copy could not get by being written again
+    fn copy_mtime(path : &Path) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::new(1_000_000_000, 123))).unwrap();
+    }
+
+    fn intent(action : &str, path : &Path, kept : &Path, target : Option<&Path>) -> Entry {
+        let e = FileEntry::new(path.to_path_buf(), &fs::metadata(path).unwrap(), 0, false);
+        Entry {
+            time       : String::new(),
+            step       : Step::Intent,
+            action     : action.to_string(),
+            path       : e.path.clone(),
+            kept       : kept.to_path_buf(),
+            target     : target.map(Path::to_path_buf),
+            algorithm  : Algorithm::Xxh3.name().to_string(),
+            hash       : hashing::to_hex(&hashing::hash_file(kept, Algorithm::Xxh3).unwrap()),
+            size       : e.size,
+            mtime      : e.mtime,
+            mtime_nsec : e.mtime_nsec,
+        }
+    }
+
+    //  the copy is back as a file of its own with its contents and mtime
+    fn assert_restored(entry : &Entry) {
+        let md = fs::symlink_metadata(&entry.path).unwrap();
+        assert!(is_original(entry, &md));
+        assert!(!is_kept(entry, &md));
+        assert_eq!(fs::read(&entry.path).unwrap(), b"same contents");
+    }
+
+    #[test]
+    fn undoes_a_delete() {
+        let (_dir, kept, copy) = setup();
+        let entry = intent("delete", &copy, &kept, None);
+        fs::remove_file(&copy).unwrap();
+
+        assert!(matches!(undo_entry(&entry), Ok(Undone::Reversed)));
+        assert_restored(&entry);
+    }
+
+    #[test]
+    fn undoes_a_hardlink() {
+        let (_dir, kept, copy) = setup();
+        let entry = intent("hardlink", &copy, &kept, None);
+        fs::remove_file(&copy).unwrap();
+        fs::hard_link(&kept, &copy).unwrap();
+
+        assert!(matches!(undo_entry(&entry), Ok(Undone::Reversed)));
+        assert_restored(&entry);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn undoes_a_symlink() {
+        let (_dir, kept, copy) = setup();
+        let entry = intent("symlink", &copy, &kept, Some(&kept));
+        fs::remove_file(&copy).unwrap();
+        std::os::unix::fs::symlink(&kept, &copy).unwrap();
+
+        assert!(matches!(undo_entry(&entry), Ok(Undone::Reversed)));
+        assert_restored(&entry);
+    }
+
+    #[test]
+    fn undoes_a_quarantine() {
+        let (dir, kept, copy) = setup();
+        let quarantined = quarantine::destination(&dir.path().join("q"), &copy);
+        let entry = intent("quarantine", &copy, &kept, Some(&quarantined));
+        quarantine::move_file(&copy, &quarantined).unwrap();
+
+        assert!(matches!(undo_entry(&entry), Ok(Undone::Reversed)));
+        assert_restored(&entry);
+        assert!(!quarantined.exists());
+    }
+
+    #[test]
+    fn leaves_files_the_run_did_not_get_to() {
+        let (_dir, kept, copy) = setup();
+        let entry = intent("delete", &copy, &kept, None);
+
+        assert!(matches!(undo_entry(&entry), Ok(Undone::Untouched(_))));
+        assert_restored(&entry);
+    }
+
+    #[test]
+    fn never_replaces_a_file_written_since() {
+        let (_dir, kept, copy) = setup();
+        let entry = intent("delete", &copy, &kept, None);
+        fs::remove_file(&copy).unwrap();
+        fs::write(&copy, b"other contents").unwrap();
+
+        assert!(undo_entry(&entry).is_err());
+        assert_eq!(fs::read(&copy).unwrap(), b"other contents");
+    }
+}
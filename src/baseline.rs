@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::output::json::{RawPath, FORMAT_NAME, FORMAT_VERSION};
+use crate::output::Report;
+use crate::walk::{FileEntry, Root};
+use crate::Config;
+
+//-------------------------------------------------------------------------------------------------
+//  the parts of an earlier --format json report a rescan is compared against, anything else in
+//  the file is ignored
+#[derive(Deserialize)]
+pub struct Baseline {
+    format     : String,
+    version    : u32,
+    parameters : Parameters,
+    groups     : Vec<Group>,
+}
+
+#[derive(Deserialize)]
+struct Parameters {
+    roots        : Vec<RootParam>,
+    mode         : String,
+    pattern      : String,
+    filter       : String,
+    size_filter  : u64,
+    hash         : String,
+    partial_size : u64,
+    verify       : String,
+}
+
+#[derive(Deserialize)]
+struct RootParam {
+    path : RawPath,
+}
+
+#[derive(Deserialize)]
+struct Group {
+    key     : String,
+    members : Vec<Member>,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    path : RawPath,
+    size : u64,
+}
+
+impl Member {
+    fn path(&self) -> PathBuf {
+        self.path.clone().into_path()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    New,
+    Grown,
+    Shrunk,
+    Changed,
+    Resolved,
+}
+
+impl ChangeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::New      => "new",
+            ChangeKind::Grown    => "grown",
+            ChangeKind::Shrunk   => "shrunk",
+            ChangeKind::Changed  => "changed",
+            ChangeKind::Resolved => "resolved",
+        }
+    }
+}
+
+//  one group that is not what it was in the baseline
+pub struct GroupChange {
+    pub kind    : ChangeKind,
+    pub key     : String,
+    pub size    : u64,
+    pub before  : usize,
+    pub after   : usize,
+    pub added   : Vec<PathBuf>,
+    pub removed : Vec<PathBuf>,
+}
+
+impl GroupChange {
+    //  every copy past the first is duplication
+    pub fn wasted_before(&self) -> u64 {
+        self.size * self.before.saturating_sub(1) as u64
+    }
+
+    pub fn wasted_after(&self) -> u64 {
+        self.size * self.after.saturating_sub(1) as u64
+    }
+}
+
+pub struct Changes {
+    pub groups        : Vec<GroupChange>,
+    pub bytes_added   : u64,
+    pub bytes_removed : u64,
+}
+
+impl Changes {
+    pub fn count(&self, kind : ChangeKind) -> usize {
+        self.groups.iter().filter(|g| g.kind == kind).count()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn load(path : &Path) -> io::Result<Baseline> {
+
+    let baseline : Baseline = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+    if baseline.format != FORMAT_NAME || baseline.version != FORMAT_VERSION {
+        let message = format!("expected a {} version {} json report, found {} version {}",
+                                FORMAT_NAME, FORMAT_VERSION, baseline.format, baseline.version);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+
+    Ok(baseline)
+}
+
+impl Baseline {
+    //  group keys are only comparable between scans that derive them the same way
+    pub fn check(&self, config : &Config) -> Result<(), String> {
+
+        let params = &self.parameters;
+        let partial_only = |verify : &str| verify == "none";
+
+        if params.mode != config.mode.name() {
+            return Err(format!("the baseline was grouped by --mode {}, this scan by {}", params.mode, config.mode.name()));
+        }
+        if params.hash != config.algorithm.name() {
+            return Err(format!("the baseline was hashed with --hash {}, this scan with {}", params.hash, config.algorithm.name()));
+        }
+        if partial_only(&params.verify) != partial_only(config.verify.name()) {
+            return Err(format!("the baseline was made with --verify {}, this scan with {}", params.verify, config.verify.name()));
+        }
+        if partial_only(&params.verify) && params.partial_size != config.partial_size {
+            return Err(format!("the baseline was made with --partial-size {}, this scan with {}",
+                                params.partial_size / 1024, config.partial_size / 1024));
+        }
+
+        //  files or groups one scan left out would show up as changes
+        if params.size_filter != config.size_filter {
+            return Err(format!("the baseline was made with --size {}, this scan with {}", params.size_filter, config.size_filter));
+        }
+        if params.pattern != config.pattern {
+            return Err(format!("the baseline was made with --pattern '{}', this scan with '{}'", params.pattern, config.pattern));
+        }
+        if params.filter != config.skip_pattern {
+            return Err(format!("the baseline was made with --filter '{}', this scan with '{}'", params.filter, config.skip_pattern));
+        }
+
+        Ok(())
+    }
+
+    //  roots that differ are allowed, but everything under a root only one scan had is reported
+    //  as a change; `roots` are the ones walked, as reports list them
+    pub fn roots_differ(&self, roots : &[Root]) -> bool {
+        let before : HashSet<PathBuf> = self.parameters.roots.iter().map(|r| r.path.clone().into_path()).collect();
+        before.len() != roots.len() || roots.iter().any(|r| !before.contains(&r.path))
+    }
+
+    pub fn compare(&self, report : &Report) -> Changes {
+        self.compare_groups(report.duplicate_groups().map(|(key, _, val)| (key.as_str(), val.as_slice())))
+    }
+
+    //  matches groups by key, and groups sharing a key (split by --verify bytes) by the paths
+    //  they have in common; biggest changes in duplication first within each kind
+    fn compare_groups<'g>(&self, report : impl Iterator<Item = (&'g str, &'g [&'g FileEntry])>) -> Changes {
+
+        //  indices of the baseline groups not matched yet, by key
+        let mut before : HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, group) in self.groups.iter().enumerate() {
+            before.entry(group.key.as_str()).or_default().push(i);
+        }
+        let mut groups = vec![];
+
+        for (key, val) in report {
+            let paths : Vec<PathBuf> = val.iter().map(|e| e.path.clone()).collect();
+            let new : HashSet<&PathBuf> = paths.iter().collect();
+            let size = val[0].size;
+
+            let matched = before.get_mut(key).filter(|c| !c.is_empty()).map(|candidates| {
+                let overlap = |i : usize| self.groups[i].members.iter().filter(|m| new.contains(&m.path())).count();
+                let best = (0..candidates.len()).max_by_key(|&n| (overlap(candidates[n]), std::cmp::Reverse(n))).unwrap();
+                &self.groups[candidates.remove(best)]
+            });
+
+            let change = match matched {
+                None        => GroupChange {
+                    kind    : ChangeKind::New,
+                    key     : key.to_string(),
+                    size,
+                    before  : 0,
+                    after   : paths.len(),
+                    added   : paths,
+                    removed : vec![],
+                },
+                Some(group) => {
+                    let old_paths : Vec<PathBuf> = group.members.iter().map(Member::path).collect();
+                    let old : HashSet<&PathBuf> = old_paths.iter().collect();
+
+                    //  as many members as before can still be other files
+                    let kind = match paths.len().cmp(&old_paths.len()) {
+                        std::cmp::Ordering::Greater => ChangeKind::Grown,
+                        std::cmp::Ordering::Less    => ChangeKind::Shrunk,
+                        std::cmp::Ordering::Equal if old == new => continue,
+                        std::cmp::Ordering::Equal   => ChangeKind::Changed,
+                    };
+
+                    GroupChange {
+                        kind,
+                        key     : key.to_string(),
+                        size,
+                        before  : old_paths.len(),
+                        after   : paths.len(),
+                        added   : paths.iter().filter(|p| !old.contains(p)).cloned().collect(),
+                        removed : old_paths.iter().filter(|p| !new.contains(p)).cloned().collect(),
+                    }
+                }
+            };
+            groups.push(change);
+        }
+
+        let unmatched : HashSet<usize> = before.into_values().flatten().collect();
+        groups.extend(self.groups.iter()
+                        .enumerate()
+                        .filter(|(i, _)| unmatched.contains(i))
+                        .map(|(_, g)| GroupChange {
+                            kind    : ChangeKind::Resolved,
+                            key     : g.key.clone(),
+                            size    : g.members.first().map(|m| m.size).unwrap_or(0),
+                            before  : g.members.len(),
+                            after   : 0,
+                            added   : vec![],
+                            removed : g.members.iter().map(Member::path).collect(),
+                        }));
+
+        groups.sort_by(|a, b| {
+            let delta = |g : &GroupChange| (g.wasted_after() as i128 - g.wasted_before() as i128).abs();
+            a.kind.cmp(&b.kind)
+                .then_with(|| delta(b).cmp(&delta(a)))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+
+        let bytes_added = groups.iter().map(|g| g.wasted_after().saturating_sub(g.wasted_before())).sum();
+        let bytes_removed = groups.iter().map(|g| g.wasted_before().saturating_sub(g.wasted_after())).sum();
+
+        Changes { groups, bytes_added, bytes_removed }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn member(path : &str) -> FileEntry {
+        FileEntry { path : PathBuf::from(path), size : 10, mtime : 0, mtime_nsec : 0, dev : 1, ino : 0, root : 0, reference : false }
+    }
+
+    fn baseline(groups : &[(&str, &[&str])]) -> Baseline {
+        let groups : Vec<_> = groups.iter()
+                                .map(|(key, paths)| json!({
+                                    "key"     : key,
+                                    "members" : paths.iter().map(|p| json!({"path" : p, "size" : 10})).collect::<Vec<_>>(),
+                                }))
+                                .collect();
+        baseline_of(json!(groups))
+    }
+
+    fn baseline_of(groups : serde_json::Value) -> Baseline {
+        serde_json::from_value(json!({
+            "format"     : FORMAT_NAME,
+            "version"    : FORMAT_VERSION,
+            "parameters" : {"roots" : [], "mode" : "content", "pattern" : ".*", "filter" : "", "size_filter" : 0,
+                            "hash" : "xxh3", "partial_size" : 4096, "verify" : "hash"},
+            "groups"     : groups,
+        })).unwrap()
+    }
+
+    //  the kind, key, added and removed paths of every change
+    fn compare(before : &Baseline, after : &[(&str, &[&str])]) -> Vec<(ChangeKind, String, Vec<String>, Vec<String>)> {
+        let entries : Vec<Vec<FileEntry>> = after.iter().map(|(_, paths)| paths.iter().map(|p| member(p)).collect()).collect();
+        let refs : Vec<Vec<&FileEntry>> = entries.iter().map(|v| v.iter().collect()).collect();
+        let names = |paths : &[PathBuf]| paths.iter().map(|p| p.to_string_lossy().into_owned()).collect();
+
+        before.compare_groups(after.iter().zip(&refs).map(|((key, _), val)| (*key, val.as_slice())))
+            .groups
+            .iter()
+            .map(|g| (g.kind, g.key.clone(), names(&g.added), names(&g.removed)))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_groups_are_not_reported() {
+        let before = baseline(&[("k1", &["/a", "/b"])]);
+        assert!(compare(&before, &[("k1", &["/b", "/a"])]).is_empty());
+    }
+
+    #[test]
+    fn every_kind_of_change() {
+        let before = baseline(&[("grown", &["/a", "/b"]), ("shrunk", &["/c", "/d", "/e"]), ("changed", &["/f", "/g"]), ("resolved", &["/h", "/i"])]);
+        let after : &[(&str, &[&str])] = &[("new", &["/x", "/y"]), ("grown", &["/a", "/b", "/j"]), ("shrunk", &["/c", "/d"]), ("changed", &["/f", "/k"])];
+
+        let s = |paths : &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(compare(&before, after), vec![
+            (ChangeKind::New,      "new".to_string(),      s(&["/x", "/y"]), s(&[])),
+            (ChangeKind::Grown,    "grown".to_string(),    s(&["/j"]),       s(&[])),
+            (ChangeKind::Shrunk,   "shrunk".to_string(),   s(&[]),           s(&["/e"])),
+            (ChangeKind::Changed,  "changed".to_string(),  s(&["/k"]),       s(&["/g"])),
+            (ChangeKind::Resolved, "resolved".to_string(), s(&[]),           s(&["/h", "/i"])),
+        ]);
+    }
+
+    //  --verify bytes can leave several groups with the same key
+    #[test]
+    fn groups_sharing_a_key_are_matched_by_their_paths() {
+        let before = baseline(&[("k", &["/a", "/b"]), ("k", &["/c", "/d"])]);
+
+        assert!(compare(&before, &[("k", &["/c", "/d"]), ("k", &["/a", "/b"])]).is_empty());
+
+        let changes = compare(&before, &[("k", &["/c", "/d", "/e"])]);
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].0, changes[0].2.clone()), (ChangeKind::Grown, vec!["/e".to_string()]));
+        assert_eq!((changes[1].0, changes[1].3.clone()), (ChangeKind::Resolved, vec!["/a".to_string(), "/b".to_string()]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_utf8_stay_apart() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let before = baseline_of(json!([{"key" : "k", "members" : [{"path" : [47, 97, 255], "size" : 10}, {"path" : "/b", "size" : 10}]}]));
+
+        let mut a = member("/b");
+        a.path = PathBuf::from(OsStr::from_bytes(b"/a\xfe"));
+        let b = member("/b");
+        let changes = before.compare_groups(vec![("k", &[&a, &b][..])].into_iter());
+        assert_eq!(changes.groups.len(), 1);
+        assert_eq!(changes.groups[0].kind, ChangeKind::Changed);
+    }
+}
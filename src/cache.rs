@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection};
+
+use crate::hashing::{Algorithm, Digest};
+use crate::output::path_bytes;
+use crate::walk::FileEntry;
+
+const SCHEMA : &str = "
+CREATE TABLE IF NOT EXISTS hashes (
+    device       INTEGER NOT NULL,
+    inode        INTEGER NOT NULL,
+    algorithm    TEXT    NOT NULL,
+    partial_size INTEGER NOT NULL,
+    size         INTEGER NOT NULL,
+    mtime        INTEGER NOT NULL,
+    mtime_nsec   INTEGER NOT NULL,
+    digest       BLOB    NOT NULL,
+    path         BLOB    NOT NULL,
+    PRIMARY KEY (device, inode, algorithm, partial_size)
+);
+";
+
+//  which file and which of its digests; full digests are stored with a partial size of 0
+type Key = (u64, u64, u64);
+
+//  what the file looked like when it was hashed, size and mtime in seconds and nanoseconds; a
+//  digest is only used while all of them still match
+type Stamp = (u64, i64, u32);
+
+//-------------------------------------------------------------------------------------------------
+fn key(e : &FileEntry, partial_size : u64) -> Key {
+    (e.dev, e.ino, partial_size)
+}
+
+fn stamp(e : &FileEntry) -> Stamp {
+    (e.size, e.mtime, e.mtime_nsec)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  $XDG_CACHE_HOME/lsdups/hashes.sqlite, falling back to ~/.cache
+pub fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("lsdups").join("hashes.sqlite"))
+}
+
+//-------------------------------------------------------------------------------------------------
+fn open(path : &Path) -> rusqlite::Result<Connection> {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  partial and full digests of earlier scans, keyed by device and inode and only trusted while
+//  size and mtime are unchanged; loaded up front so hashing threads never touch the database,
+//  new digests are written back in one go by `save`
+pub struct HashCache {
+    path      : PathBuf,
+    algorithm : Algorithm,
+    known     : HashMap<Key, (Stamp, Digest)>,
+    fresh     : Mutex<Vec<(Key, Stamp, Digest, PathBuf)>>,
+    hits      : AtomicUsize,
+    misses    : AtomicUsize,
+}
+
+impl HashCache {
+    pub fn load(path : &Path, algorithm : Algorithm) -> rusqlite::Result<HashCache> {
+
+        let conn = open(path)?;
+        let mut select = conn.prepare("SELECT device, inode, partial_size, size, mtime, mtime_nsec, digest FROM hashes WHERE algorithm = ?1")?;
+
+        let known = select.query_map(params![algorithm.name()], |row| {
+                                let key : Key = (row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64);
+                                let stamp : Stamp = (row.get::<_, i64>(3)? as u64, row.get(4)?, row.get(5)?);
+                                Ok((key, (stamp, row.get(6)?)))
+                            })?
+                            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(HashCache {
+            path      : path.to_path_buf(),
+            algorithm,
+            known,
+            fresh     : Mutex::new(vec![]),
+            hits      : AtomicUsize::new(0),
+            misses    : AtomicUsize::new(0),
+        })
+    }
+
+    //  files without an inode number cannot be told apart, they are never cached
+    pub fn lookup(&self, e : &FileEntry, partial_size : u64) -> Option<Digest> {
+        if e.ino == 0 {
+            return None;
+        }
+
+        let found = match self.known.get(&key(e, partial_size)) {
+            Some((known_stamp, digest)) if *known_stamp == stamp(e) => Some(digest.clone()),
+            _                                                        => None,
+        };
+
+        let counter = if found.is_some() {&self.hits} else {&self.misses};
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn store(&self, e : &FileEntry, partial_size : u64, digest : &[u8]) {
+        if e.ino == 0 {
+            return;
+        }
+        self.fresh.lock().unwrap().push((key(e, partial_size), stamp(e), digest.to_vec(), e.path.clone()));
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    //  replaces whatever was stored for the same inode before, which is how stale entries go;
+    //  can be called again later to write what was hashed since
+    pub fn save(&self) -> rusqlite::Result<()> {
+
+        let fresh = std::mem::take(&mut *self.fresh.lock().unwrap());
+        if fresh.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = open(&self.path)?;
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO hashes (device, inode, algorithm, partial_size, size, mtime, mtime_nsec, digest, path)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?;
+            for ((dev, ino, partial_size), (size, mtime, mtime_nsec), digest, path) in fresh {
+                insert.execute(params![
+                    dev as i64, ino as i64, self.algorithm.name(), partial_size as i64,
+                    size as i64, mtime, mtime_nsec, digest, path_bytes(&path),
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  drops entries whose file is gone or no longer matches what was hashed, returns how many
+//  entries were kept and how many were removed; paths are stored as their raw bytes so names
+//  that are not valid UTF-8 are found again
+#[cfg(unix)]
+pub fn prune(path : &Path) -> rusqlite::Result<(usize, usize)> {
+
+    use std::os::unix::fs::MetadataExt;
+    use crate::output::path_from_bytes;
+
+    //  device, inode, size, mtime and its nanoseconds, as stored and as found now
+    type Seen = (i64, i64, i64, i64, i64);
+
+    let mut conn = open(path)?;
+    let tx = conn.transaction()?;
+
+    let entries : Vec<(Seen, Vec<u8>)> = {
+        let mut select = tx.prepare("SELECT DISTINCT device, inode, size, mtime, mtime_nsec, path FROM hashes")?;
+        let rows = select.query_map([], |row| Ok(((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?), row.get(5)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+
+    let mut removed = 0;
+    {
+        let mut delete = tx.prepare("DELETE FROM hashes WHERE device = ?1 AND inode = ?2 AND size = ?3 AND mtime = ?4 AND mtime_nsec = ?5")?;
+        for (stored, file) in entries {
+            let current = fs::symlink_metadata(path_from_bytes(file))
+                            .ok()
+                            .map(|md| (md.dev() as i64, md.ino() as i64, md.len() as i64, md.mtime(), md.mtime_nsec()));
+
+            if current != Some(stored) {
+                let (dev, ino, size, mtime, mtime_nsec) = stored;
+                removed += delete.execute(params![dev, ino, size, mtime, mtime_nsec])?;
+            }
+        }
+    }
+
+    let kept : i64 = tx.query_row("SELECT COUNT(*) FROM hashes", [], |row| row.get(0))?;
+    tx.commit()?;
+
+    Ok((kept as usize, removed))
+}
+
+//  nothing is cached without inode numbers, so there is nothing to prune either
+#[cfg(not(unix))]
+pub fn prune(path : &Path) -> rusqlite::Result<(usize, usize)> {
+    let conn = open(path)?;
+    let kept : i64 = conn.query_row("SELECT COUNT(*) FROM hashes", [], |row| row.get(0))?;
+    Ok((kept as usize, 0))
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn clear(path : &Path) -> rusqlite::Result<usize> {
+    let conn = open(path)?;
+    conn.execute("DELETE FROM hashes", [])
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{File, FileTimes};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(path : &Path) -> FileEntry {
+        FileEntry::new(path.to_path_buf(), &fs::metadata(path).unwrap(), 0, false)
+    }
+
+    fn set_mtime(path : &Path, secs : u64) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_times(FileTimes::new().set_modified(UNIX_EPOCH + Duration::new(secs, 500))).unwrap();
+    }
+
+    //  a cache at `db` with the full digest of `path` saved in it, loaded again from disk
+    fn cached(db : &Path, path : &Path) -> HashCache {
+        let cache = HashCache::load(db, Algorithm::Xxh3).unwrap();
+        cache.store(&entry(path), 0, b"digest");
+        cache.save().unwrap();
+        HashCache::load(db, Algorithm::Xxh3).unwrap()
+    }
+
+    #[test]
+    fn an_unchanged_file_is_a_hit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a");
+        fs::write(&path, b"contents").unwrap();
+
+        let cache = cached(&dir.path().join("cache.sqlite"), &path);
+        assert_eq!(cache.lookup(&entry(&path), 0), Some(b"digest".to_vec()));
+        assert_eq!(cache.lookup(&entry(&path), 4096), None);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[test]
+    fn a_new_mtime_or_size_is_a_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a");
+        fs::write(&path, b"contents").unwrap();
+        set_mtime(&path, 1_000_000_000);
+        let cache = cached(&dir.path().join("cache.sqlite"), &path);
+
+        set_mtime(&path, 1_000_000_001);
+        assert_eq!(cache.lookup(&entry(&path), 0), None);
+
+        fs::write(&path, b"longer contents").unwrap();
+        set_mtime(&path, 1_000_000_000);
+        assert_eq!(cache.lookup(&entry(&path), 0), None);
+    }
+
+    //  2300-01-01, past what nanoseconds since the epoch fit in an i64
+    #[test]
+    fn far_future_mtimes_are_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a");
+        fs::write(&path, b"contents").unwrap();
+        set_mtime(&path, 10_413_792_000);
+
+        let cache = cached(&dir.path().join("cache.sqlite"), &path);
+        assert_eq!(cache.lookup(&entry(&path), 0), Some(b"digest".to_vec()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prune_finds_names_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("cache.sqlite");
+        let odd = dir.path().join(OsStr::from_bytes(b"z\xff"));
+        let gone = dir.path().join("gone");
+        fs::write(&odd, b"contents").unwrap();
+        fs::write(&gone, b"contents").unwrap();
+
+        let cache = HashCache::load(&db, Algorithm::Xxh3).unwrap();
+        cache.store(&entry(&odd), 0, b"digest");
+        cache.store(&entry(&gone), 0, b"digest");
+        cache.save().unwrap();
+        fs::remove_file(&gone).unwrap();
+
+        assert_eq!(prune(&db).unwrap(), (1, 1));
+    }
+}
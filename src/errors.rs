@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+//-------------------------------------------------------------------------------------------------
+//  why a path had to be skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    Permission,
+    Vanished,
+    Loop,
+    Io,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Permission => "permission",
+            ErrorKind::Vanished   => "vanished",
+            ErrorKind::Loop       => "loop",
+            ErrorKind::Io         => "io",
+        };
+        f.write_str(name)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+pub struct ScanError {
+    pub path    : PathBuf,
+    pub kind    : ErrorKind,
+    pub message : String,
+}
+
+impl ScanError {
+    pub fn from_io(path : &Path, err : &io::Error) -> ScanError {
+        let kind = match err.kind() {
+            io::ErrorKind::PermissionDenied => ErrorKind::Permission,
+            io::ErrorKind::NotFound         => ErrorKind::Vanished,
+            _                               => ErrorKind::Io,
+        };
+        ScanError { path : path.to_path_buf(), kind, message : err.to_string() }
+    }
+
+    pub fn new_loop(path : &Path, ancestor : &Path) -> ScanError {
+        let message = format!("directory loop back to {}", ancestor.to_string_lossy());
+        ScanError { path : path.to_path_buf(), kind : ErrorKind::Loop, message }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  what to do about skipped paths once the scan is over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+    Ignore,
+    Warn,
+    Fail,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  collects skipped paths from every thread of the scan
+#[derive(Default)]
+pub struct ErrorLog {
+    errors : Mutex<Vec<ScanError>>,
+}
+
+impl ErrorLog {
+    pub fn new() -> ErrorLog {
+        ErrorLog::default()
+    }
+
+    pub fn record(&self, err : ScanError) {
+        self.errors.lock().unwrap().push(err);
+    }
+
+    pub fn record_io(&self, path : &Path, err : &io::Error) {
+        self.record(ScanError::from_io(path, err));
+    }
+
+    //  sorted by kind and path, so the summary does not depend on thread scheduling
+    pub fn into_sorted(self) -> Vec<ScanError> {
+        let mut errors = self.errors.into_inner().unwrap();
+        errors.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.path.cmp(&b.path)) );
+        errors
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  prints every skipped path with its reason and a count per reason to stderr
+pub fn print_summary(errors : &[ScanError]) {
+
+    if errors.is_empty() {
+        return;
+    }
+
+    eprintln!();
+    eprintln!("skipped {} paths:", errors.len());
+    for err in errors {
+        eprintln!("  {:<12}{}: {}", err.kind.to_string(), err.path.to_string_lossy(), err.message);
+    }
+
+    let mut counts : Vec<(ErrorKind, usize)> = vec![];
+    for err in errors {
+        match counts.last_mut() {
+            Some((kind, n)) if *kind == err.kind => *n += 1,
+            _                                    => counts.push((err.kind, 1)),
+        }
+    }
+
+    let counts : Vec<String> = counts.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+    eprintln!("  ({})", counts.join(", "));
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use sha2::Digest as _;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::cache::HashCache;
+use crate::walk::FileEntry;
+
+const READ_BUFFER_SIZE : usize = 64 * 1024;
+
+pub type Digest = Vec<u8>;
+
+//-------------------------------------------------------------------------------------------------
+//  incremental digest over a file's content, one per supported algorithm
+pub trait ContentHasher {
+    fn update(&mut self, data : &[u8]);
+    fn finish(self : Box<Self>) -> Digest;
+}
+
+impl ContentHasher for Xxh3 {
+    fn update(&mut self, data : &[u8]) { Xxh3::update(self, data) }
+    fn finish(self : Box<Self>) -> Digest { self.digest128().to_be_bytes().to_vec() }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data : &[u8]) { blake3::Hasher::update(self, data); }
+    fn finish(self : Box<Self>) -> Digest { self.finalize().as_bytes().to_vec() }
+}
+
+impl ContentHasher for sha2::Sha256 {
+    fn update(&mut self, data : &[u8]) { sha2::Digest::update(self, data) }
+    fn finish(self : Box<Self>) -> Digest { self.finalize().to_vec() }
+}
+
+impl ContentHasher for crc32fast::Hasher {
+    fn update(&mut self, data : &[u8]) { crc32fast::Hasher::update(self, data) }
+    fn finish(self : Box<Self>) -> Digest { self.finalize().to_be_bytes().to_vec() }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the algorithms selectable with --hash; adding one only needs a ContentHasher impl and an
+//  entry here
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Xxh3,
+    Blake3,
+    Sha256,
+    Crc32,
+}
+
+impl Algorithm {
+    pub const ALL : [Algorithm; 4] = [Algorithm::Xxh3, Algorithm::Blake3, Algorithm::Sha256, Algorithm::Crc32];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Xxh3   => "xxh3",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Crc32  => "crc32",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Algorithm> {
+        Algorithm::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    pub fn new_hasher(self) -> Box<dyn ContentHasher> {
+        match self {
+            Algorithm::Xxh3   => Box::new(Xxh3::new()),
+            Algorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            Algorithm::Sha256 => Box::new(sha2::Sha256::new()),
+            Algorithm::Crc32  => Box::new(crc32fast::Hasher::new()),
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  streams the whole file through the hasher, never holding more than one buffer in memory
+pub fn hash_file(path : &Path, algorithm : Algorithm) -> io::Result<Digest> {
+
+    let mut file = File::open(path)?;
+    let mut hasher = algorithm.new_hasher();
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hasher.finish())
+}
+
+//-------------------------------------------------------------------------------------------------
+//  hashes the first and last `partial_size` bytes of a file of length `size`; files no longer
+//  than both windows together are hashed whole, so the digest then equals `hash_file`
+pub fn hash_file_partial(path : &Path, size : u64, partial_size : u64, algorithm : Algorithm) -> io::Result<Digest> {
+
+    if size <= partial_size.saturating_mul(2) {
+        return hash_file(path, algorithm);
+    }
+
+    let mut file = File::open(path)?;
+    let mut hasher = algorithm.new_hasher();
+    let mut buffer = vec![0u8; partial_size as usize];
+
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+
+    file.seek(SeekFrom::Start(size - partial_size))?;
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+
+    Ok(hasher.finish())
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the partial and full digests of a scan, looked up in and added to the hash cache if one is
+//  in use
+pub struct FileHasher<'c> {
+    pub algorithm    : Algorithm,
+    pub partial_size : u64,
+    pub cache        : Option<&'c HashCache>,
+}
+
+impl FileHasher<'_> {
+    pub fn partial(&self, e : &FileEntry) -> io::Result<Digest> {
+        //  small files are hashed whole either way, they share the cache entry of the full digest
+        let window = if e.size <= self.partial_size.saturating_mul(2) {0} else {self.partial_size};
+        self.cached(e, window, || hash_file_partial(&e.path, e.size, self.partial_size, self.algorithm))
+    }
+
+    pub fn full(&self, e : &FileEntry) -> io::Result<Digest> {
+        self.cached(e, 0, || hash_file(&e.path, self.algorithm))
+    }
+
+    fn cached<F>(&self, e : &FileEntry, window : u64, hash_fn : F) -> io::Result<Digest>
+    where
+        F : FnOnce() -> io::Result<Digest>
+    {
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None        => return hash_fn(),
+        };
+
+        if let Some(digest) = cache.lookup(e, window) {
+            return Ok(digest);
+        }
+
+        let digest = hash_fn()?;
+        cache.store(e, window, &digest);
+        Ok(digest)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn to_hex(digest : &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,24 +1,90 @@
 
+mod actions;
+mod baseline;
+mod cache;
+mod errors;
+mod hashing;
+mod output;
+mod pipeline;
+mod tui;
+mod verify;
+mod walk;
+#[cfg(target_os = "linux")]
+mod watch;
+
 use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
 use std::time::Instant;
 
 use getopts::Options;
 use regex::Regex;
-use walkdir::WalkDir;
 
-type WalkDirEntryVec = Vec<walkdir::DirEntry>;
-type WalkDirEntryRVec<'a> = Vec<&'a walkdir::DirEntry>;
-type FileNameGrouping<'a> = Vec<(String, u64, WalkDirEntryRVec<'a>)>;
-type FileNameMapping<'a> = HashMap<String, WalkDirEntryRVec<'a>>;
+use actions::keep::KeepRule;
+use actions::journal::{self, Journal};
+use actions::{quarantine, undo, LinkKind, Method};
+use cache::HashCache;
+use errors::{ErrorLog, ErrorPolicy};
+use hashing::{Algorithm, FileHasher};
+use output::{Format, Report};
+use pipeline::{StageStats, Verify};
+use walk::{FileEntry, Root};
+
+type FileEntryVec = Vec<FileEntry>;
+type FileEntryRVec<'a> = Vec<&'a FileEntry>;
+type FileGroup<'a> = (String, u64, FileEntryRVec<'a>);
+type FileGrouping<'a> = Vec<FileGroup<'a>>;
+type FileNameMapping<'a> = HashMap<String, FileEntryRVec<'a>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GroupingMode {
+    Content,
+    Name,
+}
+
+impl GroupingMode {
+    fn name(self) -> &'static str {
+        match self {
+            GroupingMode::Content => "content",
+            GroupingMode::Name    => "name",
+        }
+    }
+}
+
+struct Config {
+    roots           : Vec<Root>,
+    pattern         : String,
+    skip_pattern    : String,
+    size_filter     : u64,
+    mode            : GroupingMode,
+    partial_size    : u64,
+    verify          : Verify,
+    algorithm       : Algorithm,
+    threads         : usize,
+    errors          : ErrorPolicy,
+    format          : Format,
+    output_db       : Option<PathBuf>,
+    cache           : Option<PathBuf>,
+    baseline        : Option<PathBuf>,
+    update_baseline : bool,
+    method          : Option<Method>,
+    keep            : KeepRule,
+    execute         : bool,
+    emit_script     : bool,
+    journal         : Option<PathBuf>,
+    verbose         : bool,
+}
 
 
 //-------------------------------------------------------------------------------------------------
 /*
 
 //  https://users.rust-lang.org/t/rusts-equivalent-of-cs-system-pause/4494/4
+use std::fs;
 use std::io;
 use std::io::prelude::*;
 
@@ -41,7 +107,7 @@ fn print_usage(program: &str, opts: Options) -> Option<()> {
     let path = Path::new(program);
     let filename = path.file_name()?.to_str()?;
     
-    let brief = format!("Usage: {} [options]", filename);
+    let brief = format!("Usage: {0} [scan] [options] [DIRECTORY-PATH...]\n       {0} watch [options] DIRECTORY-PATH...\n       {0} cache prune|clear [CACHE-FILE]\n       {0} restore MANIFEST-FILE\n       {0} undo JOURNAL-FILE\n       {0} tui [options] [DIRECTORY-PATH...]", filename);
     
     println!("Author: Sarang Baheti, c 2021");
     println!("Source: https://github.com/sarangbaheti/lsdups-rust");
@@ -51,21 +117,47 @@ fn print_usage(program: &str, opts: Options) -> Option<()> {
 }
 
 //-------------------------------------------------------------------------------------------------
-fn get_options(args: &Vec<String>) -> (String, String, String, u64, bool) {
+//  `interactive` is the tui, which acts on files like --delete and friends; `watching` is watch,
+//  which takes no options about reports or actions
+fn get_options(args: &[String], interactive : bool, watching : bool) -> Config {
+
+    let algorithm_names = Algorithm::ALL.iter().map(|a| a.name()).collect::<Vec<_>>().join(", ");
+    let format_names = Format::ALL.iter().map(|f| f.name()).collect::<Vec<_>>().join(", ");
 
     let mut opts = Options::new();
-    opts.optopt("d", "dir", "directory to traverse, defaults to current directory", "<DIRECTORY-PATH>");
+    opts.optmulti("d", "dir", "directory to traverse, repeat or list them after the options for several, defaults to current directory", "<DIRECTORY-PATH>");
+    opts.optmulti("", "reference", "trusted directory, its files are never reported as duplicates to remove and groups made up only of them are not reported", "<DIRECTORY-PATH>");
     opts.optopt("p", "pattern", "pattern for files, defaults to all files", "<PATTERN>");
     opts.optopt("", "filter", "pattern for files to filter out/skip, defaults to empty-string", "<SKIP-PATTERN>");
     opts.optopt("", "size", "filter all data before this size, defaults to 0", "<unsigned int>");    
+    opts.optopt("m", "mode", "grouping mode, 'content' (default) or 'name'", "<MODE>");
+    opts.optopt("", "partial-size", "KiB hashed at the head and tail of each file before full hashing, defaults to 4", "<KiB>");
+    opts.optopt("", "hash", &format!("content hash algorithm, one of {}, defaults to xxh3", algorithm_names), "<ALGORITHM>");
+    opts.optopt("", "verify", "how content matches are confirmed, 'none' (partial hash only), 'hash' (default) or 'bytes'", "<LEVEL>");
+    opts.optopt("j", "threads", "number of threads for walking and hashing, defaults to one per cpu", "<unsigned int>");
+    opts.optopt("", "errors", "what to do about unreadable paths, 'ignore', 'warn' (default) or 'fail' with a non-zero exit code", "<POLICY>");
+    opts.optopt("f", "format", &format!("report format, one of {}, defaults to text", format_names), "<FORMAT>");
+    opts.optopt("", "output-db", "also write all files, groups and scan parameters to this SQLite database", "<FILE>");
+    opts.optflagopt("", "cache", "reuse hashes of files unchanged since an earlier scan, kept in $XDG_CACHE_HOME/lsdups unless a file is given", "<FILE>");
+    opts.optopt("", "baseline", "report only what changed since this earlier --format json report, as text or json", "<FILE>");
+    opts.optflag("", "update-baseline", "replace the --baseline report with this scan once compared");
+    opts.optflag("", "delete", "remove every duplicate but the one kept by --keep, only previews the removals unless --execute is given");
+    opts.optflag("", "hardlink", "replace every duplicate but the one kept by --keep with a hard link to it, previewed unless --execute is given");
+    opts.optflagopt("", "symlink", "replace every duplicate but the one kept by --keep with a symbolic link to it, 'absolute' (default) or 'relative', previewed unless --execute is given", "<KIND>");
+    opts.optflag("", "reflink", "share the disk blocks of every duplicate with the file kept by --keep on filesystems that support it (btrfs, xfs), previewed unless --execute is given");
+    opts.optopt("", "quarantine", "move every duplicate but the one kept by --keep into this directory, under its original path, previewed unless --execute is given", "<DIRECTORY-PATH>");
+    opts.optopt("", "keep", &format!("which file of a group to keep, one of {}, defaults to first-root; files under --reference roots are always kept", KeepRule::NAMES.join(", ")), "<RULE>");
+    opts.optflag("", "execute", "carry out the action instead of previewing it");
+    opts.optopt("", "emit-script", "write the action as a shell script to review and run instead, only 'sh'; the action defaults to --delete", "<SHELL>");
+    opts.optopt("", "journal", "where --execute records every change, defaults to lsdups-journal-<time>.ndjson in the current directory", "<FILE>");
     opts.optflag("v", "verbose",  "version information and exit");
     opts.optflag("h", "help",  "prints help");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
         Err(f) => { 
-            println!("{}", f);
-            process::exit(0x0100);
+            eprintln!("{}", f);
+            process::exit(0x02);
         }
     };
 
@@ -74,12 +166,28 @@ fn get_options(args: &Vec<String>) -> (String, String, String, u64, bool) {
         process::exit(0x0);
     }
 
-    let verbose = if matches.opt_present("v") { true} else {false};
+    let verbose = matches.opt_present("v");
 
-    let dir2walk = match matches.opt_str("d") {
-        Some(s) => s,
-        None => ".".to_string(),
-    };
+    if watching {
+        let scan_only = ["format", "output-db", "baseline", "update-baseline", "delete", "hardlink", "symlink", "reflink",
+                         "quarantine", "keep", "execute", "emit-script", "journal"];
+        if let Some(name) = scan_only.iter().find(|name| matches.opt_present(name)) {
+            eprintln!("--{} is for scans, watch only reports duplicates as json events", name);
+            process::exit(0x02);
+        }
+    }
+
+    let mut roots : Vec<Root> = matches.opt_strs("d")
+                                    .into_iter()
+                                    .chain(matches.free.iter().cloned())
+                                    .map(|s| Root { path : PathBuf::from(s), reference : false })
+                                    .collect();
+    if roots.is_empty() {
+        roots.push(Root { path : PathBuf::from("."), reference : false });
+    }
+    roots.extend(matches.opt_strs("reference")
+                    .into_iter()
+                    .map(|s| Root { path : PathBuf::from(s), reference : true }));
 
     let pattern = match matches.opt_str("p") {
         Some(s) => s,
@@ -96,41 +204,209 @@ fn get_options(args: &Vec<String>) -> (String, String, String, u64, bool) {
         None    => 0
     };
 
-    return (dir2walk, pattern, skip_pattern, size_filter, verbose)
-}
+    let mode = match matches.opt_str("m").as_deref() {
+        None | Some("content") => GroupingMode::Content,
+        Some("name")           => GroupingMode::Name,
+        Some(other)            => {
+            eprintln!("unknown mode '{}', expected 'content' or 'name'", other);
+            process::exit(0x02);
+        }
+    };
 
+    let partial_size = match matches.opt_str("partial-size") {
+        Some(s) => match s.parse::<u64>().ok().filter(|&kib| kib > 0).and_then(|kib| kib.checked_mul(1024)) {
+            Some(bytes) => bytes,
+            None        => {
+                eprintln!("invalid partial-size '{}', expected a positive number of KiB", s);
+                process::exit(0x02);
+            }
+        },
+        None    => 4 * 1024
+    };
 
-//-------------------------------------------------------------------------------------------------
-fn to_mb(numbytes : u64) -> f64 {
-    (numbytes as f64) / 1024.0 / 1024.0
-}
+    let mut methods = vec![];
+    if matches.opt_present("delete") {
+        methods.push(Method::Delete);
+    }
+    if matches.opt_present("hardlink") {
+        methods.push(Method::Hardlink);
+    }
+    if matches.opt_present("symlink") {
+        let kind = match matches.opt_str("symlink") {
+            Some(s) => match LinkKind::from_name(&s) {
+                Some(kind) => kind,
+                None       => {
+                    eprintln!("unknown symlink kind '{}', expected 'relative' or 'absolute'", s);
+                    process::exit(0x02);
+                }
+            },
+            None    => LinkKind::Absolute
+        };
+        methods.push(Method::Symlink(kind));
+    }
+    if matches.opt_present("reflink") {
+        methods.push(Method::Reflink);
+    }
+    if let Some(dir) = matches.opt_str("quarantine") {
+        methods.push(Method::Quarantine(PathBuf::from(dir)));
+    }
+    if methods.len() > 1 {
+        eprintln!("only one action at a time, got --{}", methods.iter().map(|m| m.name()).collect::<Vec<_>>().join(" and --"));
+        process::exit(0x02);
+    }
 
-//-------------------------------------------------------------------------------------------------
-fn dirent_get_size(ent : &walkdir::DirEntry) -> u64 {
-    ent.metadata().unwrap().len()
-}
+    let emit_script = match matches.opt_str("emit-script").as_deref() {
+        None        => false,
+        Some("sh")  => true,
+        Some(other) => {
+            eprintln!("unknown script kind '{}', only 'sh' scripts are written", other);
+            process::exit(0x02);
+        }
+    };
+    //  a script or the tui with no action named removes the duplicates
+    let method = methods.into_iter().next().or(if emit_script || interactive {Some(Method::Delete)} else {None});
+
+    //  acting on files needs them compared byte by byte
+    let verify = match matches.opt_str("verify").as_deref() {
+        None if method.is_some() => Verify::Bytes,
+        Some("none")             => Verify::None,
+        None | Some("hash")      => Verify::Hash,
+        Some("bytes")            => Verify::Bytes,
+        Some(other)              => {
+            eprintln!("unknown verify level '{}', expected 'none', 'hash' or 'bytes'", other);
+            process::exit(0x02);
+        }
+    };
 
-//-------------------------------------------------------------------------------------------------
-fn dirent_get_size_mb(ent : &walkdir::DirEntry) -> f64 {
-    to_mb(dirent_get_size(ent))
-}
+    let algorithm = match matches.opt_str("hash") {
+        Some(s) => match Algorithm::from_name(&s) {
+            Some(a) => a,
+            None    => {
+                eprintln!("unknown hash algorithm '{}', expected one of {}", s, algorithm_names);
+                process::exit(0x02);
+            }
+        },
+        None    => Algorithm::Xxh3
+    };
 
-//-------------------------------------------------------------------------------------------------
-fn compare_direntry(a : &walkdir::DirEntry, b : &walkdir::DirEntry) -> std::cmp::Ordering {
-    dirent_get_size(b).cmp(&dirent_get_size(&a))
+    let threads = match matches.opt_str("threads") {
+        Some(s) => match s.parse::<usize>() {
+            Ok(n) => n,
+            _     => {
+                eprintln!("invalid threads '{}', expected a number, 0 for one per cpu", s);
+                process::exit(0x02);
+            }
+        },
+        None    => 0
+    };
+
+    let errors = match matches.opt_str("errors").as_deref() {
+        Some("ignore")       => ErrorPolicy::Ignore,
+        None | Some("warn")  => ErrorPolicy::Warn,
+        Some("fail")         => ErrorPolicy::Fail,
+        Some(other)          => {
+            eprintln!("unknown error policy '{}', expected 'ignore', 'warn' or 'fail'", other);
+            process::exit(0x02);
+        }
+    };
+
+    let format = match matches.opt_str("format") {
+        Some(s) => match Format::from_name(&s) {
+            Some(f) => f,
+            None    => {
+                eprintln!("unknown format '{}', expected one of {}", s, format_names);
+                process::exit(0x02);
+            }
+        },
+        None    => Format::Text
+    };
+
+    let output_db = matches.opt_str("output-db").map(PathBuf::from);
+
+    let cache = if matches.opt_present("cache") {
+        match matches.opt_str("cache").map(PathBuf::from).or_else(cache::default_path) {
+            Some(path) => Some(path),
+            None       => {
+                eprintln!("no cache directory, set XDG_CACHE_HOME or HOME or pass --cache=<FILE>");
+                process::exit(0x02);
+            }
+        }
+    } else {
+        None
+    };
+
+    let baseline = matches.opt_str("baseline").map(PathBuf::from);
+    if baseline.is_some() && format != Format::Text && format != Format::Json {
+        eprintln!("--baseline reports changes as text or json, not {}", format.name());
+        process::exit(0x02);
+    }
+
+    let update_baseline = matches.opt_present("update-baseline");
+    if update_baseline && baseline.is_none() {
+        eprintln!("--update-baseline needs a --baseline report to replace");
+        process::exit(0x02);
+    }
+
+    let keep = match matches.opt_str("keep") {
+        Some(s) => match KeepRule::from_name(&s) {
+            Ok(rule) => rule,
+            Err(err) => {
+                eprintln!("invalid keep rule '{}': {}", s, err);
+                process::exit(0x02);
+            }
+        },
+        None    => KeepRule::FirstRoot
+    };
+
+    let execute = matches.opt_present("execute");
+    if interactive && (execute || emit_script) {
+        eprintln!("the tui asks before executing and exports from its own keys, no --execute or --emit-script");
+        process::exit(0x02);
+    }
+    if execute && emit_script {
+        eprintln!("--execute cannot be combined with --emit-script, the script does the changes");
+        process::exit(0x02);
+    }
+    if emit_script && method == Some(Method::Reflink) {
+        eprintln!("--reflink cannot be written as a shell script");
+        process::exit(0x02);
+    }
+    let journal = matches.opt_str("journal").map(PathBuf::from);
+
+    if let Some(method) = &method {
+        let conflict = if mode != GroupingMode::Content {Some("--mode name")}
+                        else if verify != Verify::Bytes {Some("--verify other than bytes")}
+                        else if format != Format::Text {Some("--format other than text")}
+                        else if baseline.is_some() {Some("--baseline")}
+                        else {None};
+        if let Some(conflict) = conflict {
+            eprintln!("--{} cannot be combined with {}", method.name(), conflict);
+            process::exit(0x02);
+        }
+    }
+    else if execute {
+        eprintln!("--execute needs an action such as --delete");
+        process::exit(0x02);
+    }
+
+    Config {
+        roots, pattern, skip_pattern, size_filter, mode, partial_size, verify, algorithm, threads, errors, format, output_db, cache,
+        baseline, update_baseline, method, keep, execute, emit_script, journal, verbose,
+    }
 }
 
+
 //-------------------------------------------------------------------------------------------------
-fn is_filename_a_match(e : &walkdir::DirEntry, re : &Regex) -> bool {
-    re.is_match(&e.file_name().to_string_lossy())
+fn is_filename_a_match(name : &OsStr, re : &Regex) -> bool {
+    re.is_match(&name.to_string_lossy())
 }
 
 //-------------------------------------------------------------------------------------------------
-fn get_filename_grouping(files : &WalkDirEntryVec) -> FileNameGrouping {
+fn get_filename_grouping(files : &[FileEntry]) -> FileGrouping<'_> {
     
-    //  WalkDirEntryVec -> FileNameMapping -> FileNameGrouping
+    //  FileEntryVec -> FileNameMapping -> FileGrouping
     //      FileNameMapping  -> helps split and group vector in smaller vectors by filename
-    //      FileNameGrouping -> helps capture this information in sorted manner
+    //      FileGrouping     -> helps capture this information in sorted manner
 
     //  a very interesting take on grouping
     //  https://hoverbear.org/blog/a-journey-into-iterators/
@@ -141,97 +417,418 @@ fn get_filename_grouping(files : &WalkDirEntryVec) -> FileNameGrouping {
                         (fname, e) 
                     })
                     .fold(FileNameMapping::new(), |mut acc, (k, x)|{
-                        acc.entry(k).or_insert(vec![]).push(x);
+                        acc.entry(k).or_default().push(x);
                         acc
                     });
 
-    let mut grouping : FileNameGrouping 
+    let mut grouping : FileGrouping 
                 = mapping.into_iter()
                     .map(|(k, v)| {
                         let vsize = v.iter()
-                            .map(|e| dirent_get_size(e))
-                            .fold(0, |acc, num| acc + num);
+                            .map(|e| e.size)
+                            .sum();
                         
                         (k, vsize, v)
                     })
                     .collect();
 
-    //  sort descending
-    grouping.sort_by(|a, b| b.1.cmp(&a.1) );
+    sort_grouping(&mut grouping);
     
     grouping
 }
 
 //-------------------------------------------------------------------------------------------------
-fn is_hidden(entry: &walkdir::DirEntry) -> bool {
-    entry.file_name()
-         .to_str()
+fn sort_grouping(grouping : &mut FileGrouping) {
+    //  sort descending, ties broken by key so reports are stable between runs
+    grouping.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)) );
+}
+
+//-------------------------------------------------------------------------------------------------
+fn group_files<'a, F>(config : &Config, files : &'a [FileEntry], cache : Option<&HashCache>, log : &ErrorLog, mut on_group : F) -> Vec<StageStats>
+where
+    F : FnMut(FileGroup<'a>)
+{
+    let hasher = FileHasher { algorithm : config.algorithm, partial_size : config.partial_size, cache };
+
+    match config.mode {
+        GroupingMode::Content => pipeline::get_content_grouping(files, &hasher, config.verify, log, on_group),
+        GroupingMode::Name    => {
+            get_filename_grouping(files).into_iter().for_each(&mut on_group);
+            vec![]
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_str()
          .map(|s| s.starts_with("."))
          .unwrap_or(false)
 }
 
+//-------------------------------------------------------------------------------------------------
+#[cfg(target_os = "linux")]
+fn watch<F>(config : &Config, roots : &[Root], accept : &F, hasher : FileHasher, out : &mut dyn io::Write) -> io::Result<()>
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    watch::run(config, roots, accept, hasher, out)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch<F>(_config : &Config, _roots : &[Root], _accept : &F, _hasher : FileHasher, _out : &mut dyn io::Write) -> io::Result<()>
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    Err(io::Error::other("watching needs inotify, which is only there on linux"))
+}
+
+//-------------------------------------------------------------------------------------------------
+//  previews the action, writes it as a script, or carries it out with every change recorded in
+//  the journal
+fn run_action(config : &Config, report : &Report, out : &mut dyn io::Write) -> io::Result<actions::Outcome> {
+
+    let method = config.method.as_ref().unwrap();
+    if config.emit_script {
+        return actions::script::write(report, method, out);
+    }
+    if !config.execute {
+        return actions::run(report, method, None, out);
+    }
+
+    let path = config.journal.clone().unwrap_or_else(journal::default_path);
+    let mut journal = match Journal::open(&path, config.algorithm) {
+        Ok(journal) => journal,
+        Err(err)    => {
+            eprintln!("failed to open journal {}: {}", path.to_string_lossy(), err);
+            process::exit(0x02);
+        }
+    };
+
+    let outcome = actions::run(report, method, Some(&mut journal), out);
+    eprintln!("changes recorded in {}", path.to_string_lossy());
+    outcome
+}
+
+//-------------------------------------------------------------------------------------------------
+//  written next to the old report and renamed over it, so a failed write leaves the old one
+fn update_baseline(report : &Report, path : &Path) -> io::Result<()> {
+
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = io::BufWriter::new(fs::File::create(&tmp_path)?);
+    output::write(report, Format::Json, &mut file)?;
+    file.into_inner()?.sync_all()?;
+
+    fs::rename(&tmp_path, path)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  lsdups cache prune|clear [CACHE-FILE]
+fn cache_command(args : &[String]) {
+
+    let path = match args.get(3).map(PathBuf::from).or_else(cache::default_path) {
+        Some(path) => path,
+        None       => {
+            eprintln!("no cache directory, set XDG_CACHE_HOME or HOME or pass the cache file");
+            process::exit(0x02);
+        }
+    };
+
+    let result = match args.get(2).map(String::as_str) {
+        Some("prune") => cache::prune(&path).map(|(kept, removed)| {
+                            println!("removed {} stale entries, {} left", removed, kept);
+                        }),
+        Some("clear") => cache::clear(&path).map(|removed| {
+                            println!("removed {} entries", removed);
+                        }),
+        _             => {
+            eprintln!("expected 'cache prune' or 'cache clear'");
+            process::exit(0x02);
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("failed to update {}: {}", path.to_string_lossy(), err);
+        process::exit(0x02);
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  lsdups restore MANIFEST-FILE
+fn restore_command(args : &[String]) {
+
+    let path = match args.get(2) {
+        Some(path) => PathBuf::from(path),
+        None       => {
+            eprintln!("expected the manifest of a quarantine, 'restore <DIRECTORY-PATH>/{}'", quarantine::MANIFEST_NAME);
+            process::exit(0x02);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+
+    match quarantine::restore(&path, &mut out) {
+        Ok((_, 0))  => {}
+        Ok(_)       => process::exit(0x02),
+        Err(err)    => {
+            eprintln!("failed to restore from {}: {}", path.to_string_lossy(), err);
+            process::exit(0x02);
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  lsdups undo JOURNAL-FILE
+fn undo_command(args : &[String]) {
+
+    let path = match args.get(2) {
+        Some(path) => PathBuf::from(path),
+        None       => {
+            eprintln!("expected the journal of an --execute run, 'undo <JOURNAL-FILE>'");
+            process::exit(0x02);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+
+    match undo::undo(&path, &mut out) {
+        Ok((_, _, 0)) => {}
+        Ok(_)         => process::exit(0x02),
+        Err(err)      => {
+            eprintln!("failed to undo {}: {}", path.to_string_lossy(), err);
+            process::exit(0x02);
+        }
+    }
+}
+
 //-------------------------------------------------------------------------------------------------
 fn main() {
 
-    let args: Vec<String> = env::args().collect();
-    let (dir2walk, pattern, skip_pattern, size_filter, verbose) = get_options(&args);
+    let mut args: Vec<String> = env::args().collect();
+    let mut watching = false;
+    let mut interactive = false;
+
+    match args.get(1).map(String::as_str) {
+        Some("cache")   => {
+            cache_command(&args);
+            return;
+        }
+        Some("restore") => {
+            restore_command(&args);
+            return;
+        }
+        Some("undo")    => {
+            undo_command(&args);
+            return;
+        }
+        //  scanning is what happens without a command as well
+        Some("scan")    => {
+            args.remove(1);
+        }
+        Some("watch")   => {
+            args.remove(1);
+            watching = true;
+        }
+        Some("tui")     => {
+            args.remove(1);
+            interactive = true;
+        }
+        _               => {}
+    }
+
+    let config = get_options(&args, interactive, watching);
+
+    if interactive && !io::stdout().is_terminal() {
+        eprintln!("the tui needs a terminal to draw on");
+        process::exit(0x02);
+    }
+
+    if watching && config.mode != GroupingMode::Content {
+        eprintln!("watch only compares file contents, --mode name is not supported");
+        process::exit(0x02);
+    }
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .build_global()
+        .unwrap();
     
     let start = Instant::now();
 
-    let file_re = Regex::new(format!(r"(?i){}", pattern).as_ref()).unwrap();
-    if verbose {
-        println!("pattern regex is: {:#?}", file_re)
+    let file_re = Regex::new(format!(r"(?i){}", config.pattern).as_ref()).unwrap();
+    if config.verbose {
+        eprintln!("pattern regex is: {:#?}", file_re)
+    }
+
+    let is_skip_re_empty = config.skip_pattern.is_empty();
+    let skip_re = Regex::new(format!(r"(?i){}", config.skip_pattern).as_ref()).unwrap();
+    if config.verbose {
+        eprintln!("filter regex is: {:#?}", skip_re)
+    }
+
+    let accept = |name : &OsStr| {
+        !is_hidden(name)
+            && (is_skip_re_empty || !is_filename_a_match(name, &skip_re))
+            && is_filename_a_match(name, &file_re)
+    };
+
+    let (roots, dropped_roots) = walk::dedupe_roots(config.roots.clone());
+    for (root, covering) in &dropped_roots {
+        eprintln!("skipping root {}, already covered by {}", root.to_string_lossy(), covering.to_string_lossy());
     }
 
-    let is_skip_re_empty = skip_pattern.is_empty();
-    let skip_re = Regex::new(format!(r"(?i){}", skip_pattern).as_ref()).unwrap();
-    if verbose {
-        println!("filter regex is: {:#?}", skip_re)
+    //  a baseline that cannot be compared against is reported before spending time on the scan
+    let baseline = config.baseline.as_ref().map(|path| {
+        let baseline = baseline::load(path).unwrap_or_else(|err| {
+            eprintln!("failed to read baseline {}: {}", path.to_string_lossy(), err);
+            process::exit(0x02);
+        });
+        if let Err(msg) = baseline.check(&config) {
+            eprintln!("cannot compare against {}: {}", path.to_string_lossy(), msg);
+            process::exit(0x02);
+        }
+        if baseline.roots_differ(&roots) {
+            eprintln!("baseline {} scanned other roots, files under roots only one scan had show up as changes", path.to_string_lossy());
+        }
+        baseline
+    });
+
+    //  a cache that cannot be read is not worth failing the scan over, it just starts empty
+    let cache = match &config.cache {
+        Some(path) if config.mode == GroupingMode::Content => match HashCache::load(path, config.algorithm) {
+            Ok(cache) => Some(cache),
+            Err(err)  => {
+                eprintln!("not using hash cache {}: {}", path.to_string_lossy(), err);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    if watching {
+        let hasher = FileHasher { algorithm : config.algorithm, partial_size : config.partial_size, cache : cache.as_ref() };
+        let stdout = io::stdout();
+        let mut out = io::BufWriter::new(stdout.lock());
+
+        if let Err(err) = watch(&config, &roots, &accept, hasher, &mut out) {
+            if err.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("stopped watching: {}", err);
+                process::exit(0x02);
+            }
+        }
+        return;
     }
 
-    let mut files : WalkDirEntryVec = WalkDir::new(dir2walk)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file() && !is_hidden(e))
-            .filter_map(|e| if !is_skip_re_empty && is_filename_a_match(&e, &skip_re) {None} else {Some(e)})
-            .filter_map(|e| if is_filename_a_match(&e, &file_re) {Some(e)} else {None})
-            .collect();
+    let log = ErrorLog::new();
 
-    //  Sort descending, bigger files first
-    files.sort_by(|a, b| compare_direntry(a, b) );
+    //  sorted descending, bigger files first
+    let files : FileEntryVec = walk::walk(&roots, &accept, &log);
 
-    let filename_grouping = get_filename_grouping(&files);
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
 
-    let total_size = files
+    //  ndjson goes out group by group while the scan runs, everything else is written at the end
+    let mut stream = if config.format == Format::Ndjson {Some(output::ndjson::Stream::new())} else {None};
+    let mut stream_result = Ok(());
+
+    let mut grouping : FileGrouping = vec![];
+    let mut total_size_dups : u64 = 0;
+
+    let stages = group_files(&config, &files, cache.as_ref(), &log, |group| {
+        //  duplicates among the trusted files alone are not what a reference scan asks about
+        if !group.2.iter().any(|e| !e.reference) {
+            return;
+        }
+
+        if group.2.len() > 1 {
+            total_size_dups += group.1;
+        }
+
+        if let Some(stream) = &mut stream {
+            if stream_result.is_ok() && output::is_reported(&group, config.size_filter) {
+                stream_result = stream.write_group(&group, &mut out);
+            }
+        }
+
+        //  the database export needs the groups even when they were streamed already
+        if stream.is_none() || config.output_db.is_some() {
+            grouping.push(group);
+        }
+    });
+
+    sort_grouping(&mut grouping);
+
+    if let Some(cache) = cache {
+        if config.verbose {
+            eprintln!("hash cache: {} hits, {} misses", cache.hits(), cache.misses());
+        }
+        if let Err(err) = cache.save() {
+            eprintln!("failed to update hash cache: {}", err);
+        }
+    }
+
+    let total_size : u64 = files
                         .iter()
-                        .map(|e| dirent_get_size(e))
-                        .fold(0, |acc, num| acc + num);
+                        .map(|e| e.size)
+                        .sum();
+
+    let report = Report {
+        config   : &config,
+        roots    : &roots,
+        files    : &files,
+        grouping,
+        stages,
+        errors   : log.into_sorted(),
+        elapsed  : start.elapsed(),
+        total_size,
+        total_size_dups,
+    };
 
-    let total_size_dups = filename_grouping
-                            .iter()
-                            .filter_map(|(_, vsize, val)| if val.len() < 2 {None} else {Some(vsize)})
-                            .fold(0, |acc, num| acc + num);
+    let mut failed = 0;
 
-    
-    println!("found {} files in {} ms", files.len(), start.elapsed().as_millis());
-    println!();
-    println!("total size for {} files is         {:.3} MB", files.len(), to_mb(total_size));
-    println!("total size for duplicated files is {:.3} MB", to_mb(total_size_dups));
-    println!();
+    let result = match (stream, &baseline) {
+        (Some(stream), _)     => stream_result.and_then(|_| stream.finish(&report, &mut out)),
+        (None, _) if interactive             => tui::run(&report, config.method.as_ref().unwrap(), &mut out).map(|outcome| failed = outcome.failed),
+        (None, _) if config.method.is_some() => run_action(&config, &report, &mut out).map(|outcome| failed = outcome.failed),
+        (None, Some(earlier)) => {
+            let changes = earlier.compare(&report);
+            output::changes::write(&changes, config.baseline.as_deref().unwrap(), config.format, &mut out)
+        }
+        (None, None)          => output::write(&report, config.format, &mut out),
+    };
 
-    for (key, vsize, val) in filename_grouping {
+    if let Err(err) = result.and_then(|_| io::Write::flush(&mut out)) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("failed to write report: {}", err);
+            process::exit(0x02);
+        }
+    }
 
-        if !verbose && val.len() < 2 || vsize < size_filter {
-            continue;
+    if config.update_baseline {
+        let path = config.baseline.as_deref().unwrap();
+        if let Err(err) = update_baseline(&report, path) {
+            eprintln!("failed to update baseline {}: {}", path.to_string_lossy(), err);
+            process::exit(0x02);
         }
+    }
 
-        println!("\n{} * {}, totalSize: {:.3}", key, val.len(), to_mb(vsize));
-        println!("----------------------------------------");
-        for v in val {
-            println!("{:6.3}   {}", dirent_get_size_mb(v), v.path().to_string_lossy());
+    if let Some(db_path) = &config.output_db {
+        if let Err(err) = output::sqlite::export(&report, db_path) {
+            eprintln!("failed to write {}: {}", db_path.to_string_lossy(), err);
+            process::exit(0x02);
         }
     }
 
-    println!();
-}
+    if config.errors != ErrorPolicy::Ignore {
+        errors::print_summary(&report.errors);
+    }
 
+    if failed > 0 || config.errors == ErrorPolicy::Fail && !report.errors.is_empty() {
+        process::exit(0x02);
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::json::RawPath;
+use super::text::to_mb;
+use super::Format;
+use crate::baseline::{ChangeKind, Changes, GroupChange};
+
+pub const FORMAT_NAME : &str = "lsdups-rust-changes";
+pub const FORMAT_VERSION : u32 = 1;
+
+//-------------------------------------------------------------------------------------------------
+#[derive(Serialize)]
+struct Document<'a> {
+    format   : &'static str,
+    version  : u32,
+    baseline : RawPath,
+    totals   : Totals,
+    changes  : Vec<Change<'a>>,
+}
+
+#[derive(Serialize)]
+struct Totals {
+    new           : usize,
+    grown         : usize,
+    shrunk        : usize,
+    changed       : usize,
+    resolved      : usize,
+    bytes_added   : u64,
+    bytes_removed : u64,
+}
+
+#[derive(Serialize)]
+struct Change<'a> {
+    #[serde(rename = "type")]
+    kind    : &'static str,
+    key     : &'a str,
+    size    : u64,
+    before  : usize,
+    after   : usize,
+    added   : Vec<RawPath>,
+    removed : Vec<RawPath>,
+}
+
+//-------------------------------------------------------------------------------------------------
+fn write_json(changes : &Changes, baseline : &Path, out : &mut dyn Write) -> io::Result<()> {
+
+    let doc = Document {
+        format   : FORMAT_NAME,
+        version  : FORMAT_VERSION,
+        baseline : RawPath::new(baseline),
+        totals   : Totals {
+            new           : changes.count(ChangeKind::New),
+            grown         : changes.count(ChangeKind::Grown),
+            shrunk        : changes.count(ChangeKind::Shrunk),
+            changed       : changes.count(ChangeKind::Changed),
+            resolved      : changes.count(ChangeKind::Resolved),
+            bytes_added   : changes.bytes_added,
+            bytes_removed : changes.bytes_removed,
+        },
+        changes  : changes.groups.iter()
+                    .map(|g| Change {
+                        kind    : g.kind.name(),
+                        key     : &g.key,
+                        size    : g.size,
+                        before  : g.before,
+                        after   : g.after,
+                        added   : g.added.iter().map(|p| RawPath::new(p)).collect(),
+                        removed : g.removed.iter().map(|p| RawPath::new(p)).collect(),
+                    })
+                    .collect(),
+    };
+
+    serde_json::to_writer_pretty(&mut *out, &doc)?;
+    writeln!(out)
+}
+
+//-------------------------------------------------------------------------------------------------
+fn write_group(group : &GroupChange, out : &mut dyn Write) -> io::Result<()> {
+
+    writeln!(out, "\n{:<9}{} * {} -> {}, wasted: {:.3} MB -> {:.3} MB",
+                group.kind.name(), group.key, group.before, group.after, to_mb(group.wasted_before()), to_mb(group.wasted_after()))?;
+    writeln!(out, "----------------------------------------")?;
+
+    //  a resolved group lists what it used to hold, some of it may well still be there
+    let (added, removed) = if group.kind == ChangeKind::Resolved {("", "   ")} else {("+  ", "-  ")};
+    for path in &group.added {
+        writeln!(out, "{}{}", added, path.to_string_lossy())?;
+    }
+    for path in &group.removed {
+        writeln!(out, "{}{}", removed, path.to_string_lossy())?;
+    }
+
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+fn write_text(changes : &Changes, baseline : &Path, out : &mut dyn Write) -> io::Result<()> {
+
+    writeln!(out, "changes since {}", baseline.to_string_lossy())?;
+    writeln!(out)?;
+
+    for kind in &[ChangeKind::New, ChangeKind::Grown, ChangeKind::Shrunk, ChangeKind::Changed, ChangeKind::Resolved] {
+        writeln!(out, "{:<9} groups: {}", kind.name(), changes.count(*kind))?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "duplication added:   {:.3} MB", to_mb(changes.bytes_added))?;
+    writeln!(out, "duplication removed: {:.3} MB", to_mb(changes.bytes_removed))?;
+
+    for group in &changes.groups {
+        write_group(group, out)?;
+    }
+
+    writeln!(out)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  only text and json are accepted together with --baseline
+pub fn write(changes : &Changes, baseline : &Path, format : Format, out : &mut dyn Write) -> io::Result<()> {
+    match format {
+        Format::Json => write_json(changes, baseline, out),
+        _            => write_text(changes, baseline, out),
+    }?;
+    out.flush()
+}
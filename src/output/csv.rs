@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+
+use super::{path_bytes, proposed_actions, utc_timestamp, Report};
+
+//-------------------------------------------------------------------------------------------------
+//  RFC 4180: fields with a separator, quote or line break are quoted, quotes doubled
+fn write_csv_field(out : &mut dyn Write, field : &[u8]) -> io::Result<()> {
+
+    if !field.iter().any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r')) {
+        return out.write_all(field);
+    }
+
+    out.write_all(b"\"")?;
+    for (i, part) in field.split(|b| *b == b'"').enumerate() {
+        if i > 0 {
+            out.write_all(b"\"\"")?;
+        }
+        out.write_all(part)?;
+    }
+    out.write_all(b"\"")
+}
+
+//-------------------------------------------------------------------------------------------------
+//  TSV has no quoting, so tabs, line breaks and backslashes are escaped the usual way
+fn write_tsv_field(out : &mut dyn Write, field : &[u8]) -> io::Result<()> {
+
+    for b in field {
+        match b {
+            b'\t' => out.write_all(b"\\t")?,
+            b'\n' => out.write_all(b"\\n")?,
+            b'\r' => out.write_all(b"\\r")?,
+            b'\\' => out.write_all(b"\\\\")?,
+            _     => out.write_all(&[*b])?,
+        }
+    }
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+fn write_row(out : &mut dyn Write, separator : u8, fields : &[&[u8]]) -> io::Result<()> {
+
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.write_all(&[separator])?;
+        }
+        if separator == b'\t' {
+            write_tsv_field(out, field)?;
+        } else {
+            write_csv_field(out, field)?;
+        }
+    }
+    out.write_all(b"\n")
+}
+
+//-------------------------------------------------------------------------------------------------
+//  one row per member of every duplicate group; paths are written as their raw bytes so names
+//  that are not valid UTF-8 survive the round trip
+pub fn write(report : &Report, separator : u8, out : &mut dyn Write) -> io::Result<()> {
+
+    write_row(out, separator, &[b"group", b"key", b"size", b"path", b"root", b"mtime", b"action"])?;
+
+    for (i, group) in report.duplicate_groups().enumerate() {
+        let (key, _, val) = group;
+        let group_id = (i + 1).to_string();
+        let actions = proposed_actions(report, val);
+
+        for (e, action) in val.iter().zip(actions) {
+            let size = e.size.to_string();
+            let root = path_bytes(&report.roots[e.root].path);
+            let mtime = utc_timestamp(e.mtime);
+
+            write_row(out, separator, &[
+                group_id.as_bytes(),
+                key.as_bytes(),
+                size.as_bytes(),
+                &path_bytes(&e.path),
+                &root,
+                mtime.as_bytes(),
+                action.name().as_bytes(),
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(separator : u8, fields : &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![];
+        write_row(&mut out, separator, fields).unwrap();
+        out
+    }
+
+    #[test]
+    fn plain_csv_fields_are_not_quoted() {
+        assert_eq!(row(b',', &[b"1", b"/data/a b.txt"]), b"1,/data/a b.txt\n");
+    }
+
+    #[test]
+    fn csv_quotes_separators_quotes_and_line_breaks() {
+        assert_eq!(row(b',', &[b"a,b", b"x"]), b"\"a,b\",x\n");
+        assert_eq!(row(b',', &[br#"say "hi""#]), b"\"say \"\"hi\"\"\"\n");
+        assert_eq!(row(b',', &[b"two\nlines", b"cr\r"]), b"\"two\nlines\",\"cr\r\"\n");
+    }
+
+    #[test]
+    fn csv_keeps_raw_bytes() {
+        assert_eq!(row(b',', &[b"z\xff", b"q\"\xfe"]), b"z\xff,\"q\"\"\xfe\"\n");
+    }
+
+    #[test]
+    fn tsv_escapes_tabs_line_breaks_and_backslashes() {
+        assert_eq!(row(b'\t', &[b"a\tb", b"c\nd\re", br"f\g"]), b"a\\tb\tc\\nd\\re\tf\\\\g\n");
+        assert_eq!(row(b'\t', &[b"a,\"b\""]), b"a,\"b\"\n");
+    }
+}
@@ -0,0 +1,19 @@
+use std::io::{self, Write};
+
+use super::{path_bytes, Report};
+
+//-------------------------------------------------------------------------------------------------
+//  the plain fdupes/jdupes shape: the paths of a group one per line, a blank line after each
+//  group; paths are written as their raw bytes
+pub fn write(report : &Report, out : &mut dyn Write) -> io::Result<()> {
+
+    for (_, _, val) in report.duplicate_groups() {
+        for e in val {
+            out.write_all(&path_bytes(&e.path))?;
+            out.write_all(b"\n")?;
+        }
+        out.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::{proposed_actions, utc_timestamp, Action, Report};
+use crate::walk::FileEntry;
+
+const STYLE : &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.4em; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { border-bottom: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }
+th.sortable { cursor: pointer; user-select: none; }
+th.sortable:after { content: " \2195"; color: #999; }
+td.num, th.num { text-align: right; white-space: nowrap; }
+.summary td { border: none; padding: 2px 12px 2px 0; }
+.keep { color: #070; }
+.remove { color: #a00; }
+code { font-size: 0.9em; }
+input { padding: 4px; width: 30em; margin-bottom: 1em; }
+"#;
+
+const SCRIPT : &str = r#"
+function sortTable(th) {
+  var table = th.closest('table');
+  var body = table.tBodies[0];
+  var col = th.cellIndex;
+  var numeric = th.classList.contains('num');
+  var asc = th.dataset.order !== 'asc';
+  th.dataset.order = asc ? 'asc' : 'desc';
+  var rows = Array.prototype.slice.call(body.rows);
+  rows.sort(function (a, b) {
+    var x = a.cells[col].dataset.value || a.cells[col].textContent;
+    var y = b.cells[col].dataset.value || b.cells[col].textContent;
+    var c = numeric ? Number(x) - Number(y) : x.localeCompare(y);
+    return asc ? c : -c;
+  });
+  rows.forEach(function (r) { body.appendChild(r); });
+}
+function filterTable(input, id) {
+  var needle = input.value.toLowerCase();
+  var rows = document.getElementById(id).tBodies[0].rows;
+  for (var i = 0; i < rows.length; i++) {
+    rows[i].style.display = rows[i].textContent.toLowerCase().indexOf(needle) >= 0 ? '' : 'none';
+  }
+}
+"#;
+
+//-------------------------------------------------------------------------------------------------
+fn escape(text : &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&'  => escaped.push_str("&amp;"),
+            '<'  => escaped.push_str("&lt;"),
+            '>'  => escaped.push_str("&gt;"),
+            '"'  => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _    => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+//-------------------------------------------------------------------------------------------------
+fn human_size(numbytes : u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = numbytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", numbytes)
+    } else {
+        format!("{:.2} {}", size, units[unit])
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+fn write_summary(report : &Report, groups : usize, wasted : u64, out : &mut dyn Write) -> io::Result<()> {
+
+    let config = report.config;
+    let roots : Vec<String> = report.roots.iter()
+                                .map(|r| {
+                                    let kind = if r.reference {" (reference)"} else {""};
+                                    format!("{}{}", escape(&r.path.to_string_lossy()), kind)
+                                })
+                                .collect();
+
+    writeln!(out, "<table class=\"summary\">")?;
+    writeln!(out, "<tr><td>roots</td><td>{}</td></tr>", roots.join("<br>"))?;
+    writeln!(out, "<tr><td>mode</td><td>{} ({}, verify {})</td></tr>", config.mode.name(), config.algorithm.name(), config.verify.name())?;
+    writeln!(out, "<tr><td>files scanned</td><td>{}</td></tr>", report.files.len())?;
+    writeln!(out, "<tr><td>total size</td><td>{}</td></tr>", human_size(report.total_size))?;
+    writeln!(out, "<tr><td>total size of duplicated files</td><td>{}</td></tr>", human_size(report.total_size_dups))?;
+    writeln!(out, "<tr><td>duplicate groups</td><td>{}</td></tr>", groups)?;
+    writeln!(out, "<tr><td>wasted by extra copies</td><td><b>{}</b></td></tr>", human_size(wasted))?;
+    writeln!(out, "<tr><td>skipped paths</td><td>{}</td></tr>", report.errors.len())?;
+    writeln!(out, "</table>")
+}
+
+//-------------------------------------------------------------------------------------------------
+fn write_directories(directories : &[(String, u64, usize)], out : &mut dyn Write) -> io::Result<()> {
+
+    writeln!(out, "<h2>Waste per directory</h2>")?;
+    writeln!(out, "<input placeholder=\"filter directories\" oninput=\"filterTable(this, 'dirs')\">")?;
+    writeln!(out, "<table id=\"dirs\"><thead><tr>")?;
+    writeln!(out, "<th class=\"sortable\" onclick=\"sortTable(this)\">directory</th>")?;
+    writeln!(out, "<th class=\"sortable num\" onclick=\"sortTable(this)\">extra copies</th>")?;
+    writeln!(out, "<th class=\"sortable num\" onclick=\"sortTable(this)\">wasted</th>")?;
+    writeln!(out, "</tr></thead><tbody>")?;
+
+    for (dir, wasted, copies) in directories {
+        writeln!(out, "<tr><td><code>{}</code></td><td class=\"num\">{}</td><td class=\"num\" data-value=\"{}\">{}</td></tr>",
+            escape(dir), copies, wasted, human_size(*wasted))?;
+    }
+
+    writeln!(out, "</tbody></table>")
+}
+
+//-------------------------------------------------------------------------------------------------
+//  which members take up space beyond the one copy a group needs, whatever --keep makes of it:
+//  all but one file per group, the kept one if there is one, and never a hard link of a file
+//  already counted
+fn extra_copies(members : &[&FileEntry], actions : &[Action]) -> Vec<bool> {
+
+    let mut order : Vec<usize> = (0..members.len()).collect();
+    order.sort_by_key(|&i| actions[i] != Action::Keep);
+
+    let mut extra = vec![false; members.len()];
+    let mut seen = HashSet::new();
+    for (n, i) in order.into_iter().enumerate() {
+        let e = members[i];
+        let linked = e.ino != 0 && !seen.insert((e.dev, e.ino));
+        extra[i] = n > 0 && !linked;
+    }
+    extra
+}
+
+//-------------------------------------------------------------------------------------------------
+//  a single page with no external assets: the totals, a sortable and filterable table of the
+//  groups with their members folded away, and how much every directory wastes on extra copies
+pub fn write(report : &Report, out : &mut dyn Write) -> io::Result<()> {
+
+    let mut rows = vec![];
+    let mut wasted_total = 0;
+    let mut directories : HashMap<String, (u64, usize)> = HashMap::new();
+
+    for (key, vsize, val) in report.duplicate_groups() {
+        let actions = proposed_actions(report, val);
+        let extra = extra_copies(val, &actions);
+        let wasted : u64 = val.iter()
+                            .zip(&extra)
+                            .filter(|(_, extra)| **extra)
+                            .map(|(e, _)| e.size)
+                            .sum();
+        wasted_total += wasted;
+
+        let mut members = String::new();
+        for ((e, action), extra) in val.iter().zip(&actions).zip(&extra) {
+            if *extra {
+                let dir = e.path.parent().unwrap_or_else(|| Path::new("")).to_string_lossy().into_owned();
+                let entry = directories.entry(dir).or_default();
+                entry.0 += e.size;
+                entry.1 += 1;
+            }
+            members.push_str(&format!("<li><span class=\"{}\">{}</span> <code>{}</code> &middot; {} &middot; {}</li>",
+                action.name(), action.name(), escape(&e.path.to_string_lossy()), human_size(e.size), utc_timestamp(e.mtime)));
+        }
+
+        rows.push(format!(
+            "<tr><td><code>{}</code></td><td class=\"num\">{}</td><td class=\"num\" data-value=\"{}\">{}</td><td class=\"num\" data-value=\"{}\">{}</td><td><details><summary>{}</summary><ul>{}</ul></details></td></tr>",
+            escape(key), val.len(), vsize, human_size(*vsize), wasted, human_size(wasted),
+            escape(&val[0].file_name().to_string_lossy()), members));
+    }
+
+    let mut directories : Vec<(String, u64, usize)> = directories.into_iter()
+                                                        .map(|(dir, (wasted, copies))| (dir, wasted, copies))
+                                                        .collect();
+    directories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)) );
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html><head><meta charset=\"utf-8\"><title>lsdups-rust report</title>")?;
+    writeln!(out, "<style>{}</style><script>{}</script></head><body>", STYLE, SCRIPT)?;
+    writeln!(out, "<h1>Duplicate files</h1>")?;
+
+    write_summary(report, rows.len(), wasted_total, out)?;
+
+    writeln!(out, "<h2>Groups</h2>")?;
+    writeln!(out, "<input placeholder=\"filter groups by key or path\" oninput=\"filterTable(this, 'groups')\">")?;
+    writeln!(out, "<table id=\"groups\"><thead><tr>")?;
+    writeln!(out, "<th class=\"sortable\" onclick=\"sortTable(this)\">key</th>")?;
+    writeln!(out, "<th class=\"sortable num\" onclick=\"sortTable(this)\">files</th>")?;
+    writeln!(out, "<th class=\"sortable num\" onclick=\"sortTable(this)\">total size</th>")?;
+    writeln!(out, "<th class=\"sortable num\" onclick=\"sortTable(this)\">wasted</th>")?;
+    writeln!(out, "<th>members</th>")?;
+    writeln!(out, "</tr></thead><tbody>")?;
+    for row in &rows {
+        writeln!(out, "{}", row)?;
+    }
+    writeln!(out, "</tbody></table>")?;
+
+    write_directories(&directories, out)?;
+
+    writeln!(out, "</body></html>")
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn member(path : &str, ino : u64) -> FileEntry {
+        FileEntry { path : PathBuf::from(path), size : 10, mtime : 0, mtime_nsec : 0, dev : 1, ino, root : 0, reference : false }
+    }
+
+    #[test]
+    fn extra_copies_do_not_depend_on_the_keep_rule() {
+        let (a, b, c) = (member("/a", 1), member("/b", 2), member("/c", 3));
+        let members = [&a, &b, &c];
+
+        assert_eq!(extra_copies(&members, &[Action::Review; 3]), vec![false, true, true]);
+        assert_eq!(extra_copies(&members, &[Action::Remove, Action::Keep, Action::Remove]), vec![true, false, true]);
+    }
+
+    #[test]
+    fn hard_links_are_no_extra_copies() {
+        let (a, b, c) = (member("/a", 1), member("/b", 1), member("/c", 2));
+        assert_eq!(extra_copies(&[&a, &b, &c], &[Action::Review; 3]), vec![false, false, true]);
+    }
+}
@@ -0,0 +1,237 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{path_bytes, path_from_bytes, Report};
+use crate::errors::ScanError;
+use crate::walk::FileEntry;
+
+pub const FORMAT_NAME : &str = "lsdups-rust";
+
+//  bumped whenever a field changes meaning or goes away; new fields may appear without a bump
+pub const FORMAT_VERSION : u32 = 1;
+
+//-------------------------------------------------------------------------------------------------
+#[derive(Serialize)]
+struct Document<'a> {
+    format     : &'static str,
+    version    : u32,
+    parameters : Parameters<'a>,
+    totals     : Totals,
+    groups     : Vec<Group<'a>>,
+    errors     : Vec<Error>,
+}
+
+#[derive(Serialize)]
+pub struct Parameters<'a> {
+    roots        : Vec<RootParam>,
+    mode         : &'static str,
+    pattern      : &'a str,
+    filter       : &'a str,
+    size_filter  : u64,
+    hash         : &'static str,
+    partial_size : u64,
+    verify       : &'static str,
+}
+
+#[derive(Serialize)]
+struct RootParam {
+    path      : RawPath,
+    reference : bool,
+}
+
+#[derive(Serialize)]
+pub struct Totals {
+    files           : usize,
+    groups          : usize,
+    total_size      : u64,
+    total_size_dups : u64,
+}
+
+#[derive(Serialize)]
+pub struct Group<'a> {
+    key        : &'a str,
+    total_size : u64,
+    members    : Vec<Member>,
+}
+
+#[derive(Serialize)]
+struct Member {
+    path       : RawPath,
+    size       : u64,
+    mtime      : i64,
+    mtime_nsec : u32,
+    device     : u64,
+    inode      : u64,
+    root       : usize,
+    reference  : bool,
+}
+
+#[derive(Serialize)]
+pub struct Error {
+    path    : RawPath,
+    kind    : String,
+    message : String,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  a path as a json string where it is utf-8 and as an array of its raw bytes where it is not,
+//  so whatever reads it back gets the very name that was scanned
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RawPath {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl RawPath {
+    pub fn new(path : &Path) -> RawPath {
+        match path.to_str() {
+            Some(text) => RawPath::Text(text.to_string()),
+            None       => RawPath::Bytes(path_bytes(path).into_owned()),
+        }
+    }
+
+    pub fn into_path(self) -> PathBuf {
+        match self {
+            RawPath::Text(text) => PathBuf::from(text),
+            RawPath::Bytes(raw) => path_from_bytes(raw),
+        }
+    }
+}
+
+//  for `#[serde(with)]` on path fields of other json records
+pub mod raw_path {
+    use super::*;
+
+    pub fn serialize<S : Serializer>(path : &Path, serializer : S) -> Result<S::Ok, S::Error> {
+        RawPath::new(path).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<PathBuf, D::Error> {
+        RawPath::deserialize(deserializer).map(RawPath::into_path)
+    }
+}
+
+pub mod raw_path_option {
+    use super::*;
+
+    pub fn serialize<S : Serializer>(path : &Option<PathBuf>, serializer : S) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(RawPath::new).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D : Deserializer<'de>>(deserializer : D) -> Result<Option<PathBuf>, D::Error> {
+        Option::<RawPath>::deserialize(deserializer).map(|path| path.map(RawPath::into_path))
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+impl Member {
+    fn new(e : &FileEntry) -> Member {
+        Member {
+            path       : RawPath::new(&e.path),
+            size       : e.size,
+            mtime      : e.mtime,
+            mtime_nsec : e.mtime_nsec,
+            device     : e.dev,
+            inode      : e.ino,
+            root       : e.root,
+            reference  : e.reference,
+        }
+    }
+}
+
+impl<'a> Group<'a> {
+    pub fn new(key : &'a str, total_size : u64, members : &[&FileEntry]) -> Group<'a> {
+        Group { key, total_size, members : members.iter().map(|e| Member::new(e)).collect() }
+    }
+}
+
+impl Error {
+    pub fn new(err : &ScanError) -> Error {
+        Error {
+            path    : RawPath::new(&err.path),
+            kind    : err.kind.to_string(),
+            message : err.message.clone(),
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+impl<'a> Parameters<'a> {
+    pub fn new(report : &Report<'a>) -> Parameters<'a> {
+        let config = report.config;
+        Parameters {
+            roots        : report.roots.iter()
+                            .map(|r| RootParam { path : RawPath::new(&r.path), reference : r.reference })
+                            .collect(),
+            mode         : config.mode.name(),
+            pattern      : &config.pattern,
+            filter       : &config.skip_pattern,
+            size_filter  : config.size_filter,
+            hash         : config.algorithm.name(),
+            partial_size : config.partial_size,
+            verify       : config.verify.name(),
+        }
+    }
+}
+
+impl Totals {
+    pub fn new(report : &Report, groups : usize) -> Totals {
+        Totals {
+            files           : report.files.len(),
+            groups,
+            total_size      : report.total_size,
+            total_size_dups : report.total_size_dups,
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn write(report : &Report, out : &mut dyn Write) -> io::Result<()> {
+
+    let groups : Vec<Group> = report.duplicate_groups()
+                                .map(|(key, vsize, val)| Group::new(key, *vsize, val))
+                                .collect();
+
+    let doc = Document {
+        format     : FORMAT_NAME,
+        version    : FORMAT_VERSION,
+        parameters : Parameters::new(report),
+        totals     : Totals::new(report, groups.len()),
+        groups,
+        errors     : report.errors.iter().map(Error::new).collect(),
+    };
+
+    serde_json::to_writer_pretty(&mut *out, &doc)?;
+    writeln!(out)
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(path : &Path) -> (String, PathBuf) {
+        let json = serde_json::to_string(&RawPath::new(path)).unwrap();
+        let back : RawPath = serde_json::from_str(&json).unwrap();
+        (json, back.into_path())
+    }
+
+    #[test]
+    fn utf8_paths_are_strings() {
+        let path = Path::new("/data/ä b");
+        assert_eq!(round_trip(path), (r#""/data/ä b""#.to_string(), path.to_path_buf()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn other_paths_keep_their_bytes() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/z\xff"));
+        assert_eq!(round_trip(path), ("[47,122,255]".to_string(), path.to_path_buf()));
+    }
+}
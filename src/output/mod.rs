@@ -0,0 +1,183 @@
+pub mod changes;
+mod csv;
+mod fdupes;
+mod html;
+pub mod json;
+pub mod ndjson;
+mod rmlint;
+pub mod sqlite;
+pub mod text;
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors::ScanError;
+use crate::pipeline::StageStats;
+use crate::walk::{FileEntry, Root};
+use crate::{Config, FileGroup, FileGrouping, GroupingMode};
+
+//-------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Ndjson,
+    Csv,
+    Tsv,
+    Fdupes,
+    RmlintJson,
+    Html,
+}
+
+impl Format {
+    pub const ALL : [Format; 8] = [
+        Format::Text, Format::Json, Format::Ndjson, Format::Csv, Format::Tsv, Format::Fdupes, Format::RmlintJson, Format::Html,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Text       => "text",
+            Format::Json       => "json",
+            Format::Ndjson     => "ndjson",
+            Format::Csv        => "csv",
+            Format::Tsv        => "tsv",
+            Format::Fdupes     => "fdupes",
+            Format::RmlintJson => "rmlint-json",
+            Format::Html       => "html",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Format> {
+        Format::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  everything a scan found, in the shape every output format starts from
+pub struct Report<'a> {
+    pub config          : &'a Config,
+    pub roots           : &'a [Root],
+    pub files           : &'a [FileEntry],
+    pub grouping        : FileGrouping<'a>,
+    pub stages          : Vec<StageStats>,
+    pub errors          : Vec<ScanError>,
+    pub elapsed         : Duration,
+    pub total_size      : u64,
+    pub total_size_dups : u64,
+}
+
+impl<'a> Report<'a> {
+    pub fn duplicate_groups(&self) -> impl Iterator<Item = &FileGroup<'a>> {
+        let size_filter = self.config.size_filter;
+        self.grouping.iter()
+            .filter(move |group| is_reported(group, size_filter))
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the groups worth reporting: more than one member and at least --size bytes in total
+pub fn is_reported(group : &FileGroup, size_filter : u64) -> bool {
+    let (_, vsize, val) = group;
+    val.len() > 1 && *vsize >= size_filter
+}
+
+//-------------------------------------------------------------------------------------------------
+//  what a report suggests doing with each member of a group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Keep,
+    Remove,
+    Review,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Keep   => "keep",
+            Action::Remove => "remove",
+            Action::Review => "review",
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  files under reference roots are always kept, otherwise the one picked by --keep is; groups
+//  that only share a name were never compared and, like groups where --keep finds nothing to
+//  keep, are left for a human to review
+pub fn proposed_actions(report : &Report, members : &[&FileEntry]) -> Vec<Action> {
+
+    if report.config.mode == GroupingMode::Name {
+        return vec![Action::Review; members.len()];
+    }
+
+    match report.config.keep.keep_flags(members) {
+        Some(flags) => flags.into_iter().map(|keep| if keep {Action::Keep} else {Action::Remove}).collect(),
+        None        => vec![Action::Review; members.len()],
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(unix)]
+pub fn path_bytes(path : &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path : &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s)    => Cow::Owned(s.into_bytes()),
+    }
+}
+
+//  the other way round, for paths read back from where `path_bytes` put them
+#[cfg(unix)]
+pub fn path_from_bytes(raw : Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(raw))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(raw : Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&raw).into_owned())
+}
+
+//-------------------------------------------------------------------------------------------------
+//  seconds since the epoch as "YYYY-MM-DD hh:mm:ss" in UTC, days to civil date after
+//  http://howardhinnant.github.io/date_algorithms.html
+pub fn utc_timestamp(secs : i64) -> String {
+
+    let days = secs.div_euclid(86400);
+    let rem  = secs.rem_euclid(86400);
+
+    let z   = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp  = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year  = yoe + era * 400 + if month <= 2 {1} else {0};
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn write(report : &Report, format : Format, out : &mut dyn Write) -> io::Result<()> {
+    match format {
+        Format::Text       => text::write(report, out),
+        Format::Json       => json::write(report, out),
+        Format::Ndjson     => ndjson::write(report, out),
+        Format::Csv        => csv::write(report, b',', out),
+        Format::Tsv        => csv::write(report, b'\t', out),
+        Format::Fdupes     => fdupes::write(report, out),
+        Format::RmlintJson => rmlint::write(report, out),
+        Format::Html       => html::write(report, out),
+    }?;
+    out.flush()
+}
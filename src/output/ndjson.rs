@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use super::json::{self, Error, Group, Parameters, Totals};
+use super::Report;
+use crate::FileGroup;
+
+//-------------------------------------------------------------------------------------------------
+//  one line per duplicate group, tagged so consumers can tell it from the summary
+#[derive(Serialize)]
+struct GroupRecord<'a> {
+    #[serde(rename = "type")]
+    kind  : &'static str,
+    #[serde(flatten)]
+    group : Group<'a>,
+}
+
+//  the last line, written once the scan is over
+#[derive(Serialize)]
+struct SummaryRecord<'a> {
+    #[serde(rename = "type")]
+    kind       : &'static str,
+    format     : &'static str,
+    version    : u32,
+    parameters : Parameters<'a>,
+    totals     : Totals,
+    errors     : Vec<Error>,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  writes groups as they are handed over, flushing after each so a reader on the other end of
+//  a pipe sees them while the scan is still running
+#[derive(Default)]
+pub struct Stream {
+    groups : usize,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    pub fn write_group(&mut self, group : &FileGroup, out : &mut dyn Write) -> io::Result<()> {
+        let (key, vsize, val) = group;
+        let record = GroupRecord { kind : "group", group : Group::new(key, *vsize, val) };
+
+        serde_json::to_writer(&mut *out, &record)?;
+        writeln!(out)?;
+        self.groups += 1;
+        out.flush()
+    }
+
+    pub fn finish(self, report : &Report, out : &mut dyn Write) -> io::Result<()> {
+        let record = SummaryRecord {
+            kind       : "summary",
+            format     : json::FORMAT_NAME,
+            version    : json::FORMAT_VERSION,
+            parameters : Parameters::new(report),
+            totals     : Totals::new(report, self.groups),
+            errors     : report.errors.iter().map(Error::new).collect(),
+        };
+
+        serde_json::to_writer(&mut *out, &record)?;
+        writeln!(out)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  the same records for a scan whose groups were collected rather than streamed
+pub fn write(report : &Report, out : &mut dyn Write) -> io::Result<()> {
+
+    let mut stream = Stream::new();
+    for group in report.duplicate_groups() {
+        stream.write_group(group, out)?;
+    }
+    stream.finish(report, out)
+}
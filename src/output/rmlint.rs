@@ -0,0 +1,69 @@
+use std::env;
+use std::io::{self, Write};
+
+use serde_json::json;
+
+use super::{proposed_actions, Action, Report};
+
+//-------------------------------------------------------------------------------------------------
+//  the array rmlint writes with `-o json`: a header object, one object per duplicate file with
+//  the kept copy marked `is_original`, and a footer with the totals
+pub fn write(report : &Report, out : &mut dyn Write) -> io::Result<()> {
+
+    let config = report.config;
+
+    let mut records = vec![json!({
+        "description"   : "rmlint json-dump of lint files",
+        "cwd"           : env::current_dir().map(|d| d.to_string_lossy().into_owned()).unwrap_or_default(),
+        "args"          : env::args().collect::<Vec<_>>().join(" "),
+        "version"       : env!("CARGO_PKG_VERSION"),
+        "progress"      : 0,
+        "checksum_type" : config.algorithm.name(),
+    })];
+
+    let mut duplicates = 0;
+    let mut duplicate_sets = 0;
+    let mut lint_size = 0;
+
+    for (key, _, val) in report.duplicate_groups() {
+        duplicate_sets += 1;
+
+        for (e, action) in val.iter().zip(proposed_actions(report, val)) {
+            let root = &report.roots[e.root].path;
+            let depth = e.path.strip_prefix(root).map(|p| p.components().count()).unwrap_or(0);
+
+            if action != Action::Keep {
+                duplicates += 1;
+                lint_size += e.size;
+            }
+
+            records.push(json!({
+                "id"          : records.len(),
+                "type"        : "duplicate_file",
+                "progress"    : 100,
+                "checksum"    : key,
+                "path"        : e.path.to_string_lossy(),
+                "size"        : e.size,
+                "depth"       : depth,
+                "inode"       : e.ino,
+                "disk_id"     : e.dev,
+                "is_original" : action == Action::Keep,
+                "mtime"       : e.mtime as f64 + e.mtime_nsec as f64 / 1e9,
+            }));
+        }
+    }
+
+    records.push(json!({
+        "aborted"         : false,
+        "progress"        : 100,
+        "total_files"     : report.files.len(),
+        "ignored_files"   : 0,
+        "ignored_folders" : 0,
+        "duplicates"      : duplicates,
+        "duplicate_sets"  : duplicate_sets,
+        "total_lint_size" : lint_size,
+    }));
+
+    serde_json::to_writer_pretty(&mut *out, &records)?;
+    writeln!(out)
+}
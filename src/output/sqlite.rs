@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+
+use super::json::FORMAT_VERSION;
+use super::{proposed_actions, Report};
+use crate::walk::FileEntry;
+
+//  every scan written to the same database gets its own `scan_id`, so earlier scans stay
+//  queryable next to the new one
+const SCHEMA : &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id              INTEGER PRIMARY KEY,
+    format_version  INTEGER NOT NULL,
+    created_at      INTEGER NOT NULL,
+    mode            TEXT    NOT NULL,
+    hash            TEXT    NOT NULL,
+    verify          TEXT    NOT NULL,
+    partial_size    INTEGER NOT NULL,
+    pattern         TEXT    NOT NULL,
+    filter          TEXT    NOT NULL,
+    size_filter     INTEGER NOT NULL,
+    total_files     INTEGER NOT NULL,
+    total_size      INTEGER NOT NULL,
+    total_size_dups INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS roots (
+    id        INTEGER PRIMARY KEY,
+    scan_id   INTEGER NOT NULL REFERENCES scans(id),
+    idx       INTEGER NOT NULL,
+    path      TEXT    NOT NULL,
+    reference INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS groups (
+    id         INTEGER PRIMARY KEY,
+    scan_id    INTEGER NOT NULL REFERENCES scans(id),
+    key        TEXT    NOT NULL,
+    members    INTEGER NOT NULL,
+    total_size INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS files (
+    id         INTEGER PRIMARY KEY,
+    scan_id    INTEGER NOT NULL REFERENCES scans(id),
+    root_id    INTEGER NOT NULL REFERENCES roots(id),
+    group_id   INTEGER REFERENCES groups(id),
+    path       TEXT    NOT NULL,
+    dir        TEXT    NOT NULL,
+    name       TEXT    NOT NULL,
+    size       INTEGER NOT NULL,
+    mtime      INTEGER NOT NULL,
+    mtime_nsec INTEGER NOT NULL,
+    device     INTEGER NOT NULL,
+    inode      INTEGER NOT NULL,
+    action     TEXT
+);
+CREATE TABLE IF NOT EXISTS errors (
+    id      INTEGER PRIMARY KEY,
+    scan_id INTEGER NOT NULL REFERENCES scans(id),
+    path    TEXT    NOT NULL,
+    kind    TEXT    NOT NULL,
+    message TEXT    NOT NULL
+);
+CREATE INDEX IF NOT EXISTS groups_scan_key  ON groups(scan_id, key);
+CREATE INDEX IF NOT EXISTS files_scan_group ON files(scan_id, group_id);
+CREATE INDEX IF NOT EXISTS files_scan_dir   ON files(scan_id, dir);
+CREATE INDEX IF NOT EXISTS files_scan_size  ON files(scan_id, size);
+CREATE INDEX IF NOT EXISTS files_inode      ON files(device, inode);
+";
+
+//-------------------------------------------------------------------------------------------------
+//  writes every scanned file, the duplicate groups and the scan parameters into `db_path`,
+//  all in one transaction
+pub fn export(report : &Report, db_path : &Path) -> rusqlite::Result<()> {
+
+    let config = report.config;
+    let mut conn = Connection::open(db_path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+
+    let created_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    tx.execute(
+        "INSERT INTO scans (format_version, created_at, mode, hash, verify, partial_size, pattern, filter,
+                            size_filter, total_files, total_size, total_size_dups)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            FORMAT_VERSION, created_at, config.mode.name(), config.algorithm.name(), config.verify.name(),
+            config.partial_size as i64, config.pattern, config.skip_pattern, config.size_filter as i64,
+            report.files.len() as i64, report.total_size as i64, report.total_size_dups as i64,
+        ],
+    )?;
+    let scan_id = tx.last_insert_rowid();
+
+    let mut root_ids = vec![];
+    {
+        let mut insert = tx.prepare("INSERT INTO roots (scan_id, idx, path, reference) VALUES (?1, ?2, ?3, ?4)")?;
+        for (i, root) in report.roots.iter().enumerate() {
+            insert.execute(params![scan_id, i as i64, root.path.to_string_lossy(), root.reference])?;
+            root_ids.push(tx.last_insert_rowid());
+        }
+    }
+
+    //  files are matched to their group and proposed action by address, they are all borrowed
+    //  from the same slice
+    let mut membership : HashMap<*const FileEntry, (i64, &'static str)> = HashMap::new();
+    {
+        let mut insert = tx.prepare("INSERT INTO groups (scan_id, key, members, total_size) VALUES (?1, ?2, ?3, ?4)")?;
+        for (key, vsize, val) in report.duplicate_groups() {
+            insert.execute(params![scan_id, key, val.len() as i64, *vsize as i64])?;
+            let group_id = tx.last_insert_rowid();
+
+            for (e, action) in val.iter().zip(proposed_actions(report, val)) {
+                membership.insert(*e as *const FileEntry, (group_id, action.name()));
+            }
+        }
+    }
+
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO files (scan_id, root_id, group_id, path, dir, name, size, mtime, mtime_nsec, device, inode, action)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)")?;
+        for e in report.files {
+            let (group_id, action) = match membership.get(&(e as *const FileEntry)) {
+                Some((group_id, action)) => (Some(*group_id), Some(*action)),
+                None                     => (None, None),
+            };
+            let dir = e.path.parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+
+            insert.execute(params![
+                scan_id, root_ids[e.root], group_id, e.path.to_string_lossy(), dir, e.file_name().to_string_lossy(),
+                e.size as i64, e.mtime, e.mtime_nsec, e.dev as i64, e.ino as i64, action,
+            ])?;
+        }
+    }
+
+    {
+        let mut insert = tx.prepare("INSERT INTO errors (scan_id, path, kind, message) VALUES (?1, ?2, ?3, ?4)")?;
+        for err in &report.errors {
+            insert.execute(params![scan_id, err.path.to_string_lossy(), err.kind.to_string(), err.message])?;
+        }
+    }
+
+    tx.commit()
+}
@@ -0,0 +1,62 @@
+use std::io::{self, Write};
+
+use super::Report;
+
+//-------------------------------------------------------------------------------------------------
+pub fn to_mb(numbytes : u64) -> f64 {
+    (numbytes as f64) / 1024.0 / 1024.0
+}
+
+//-------------------------------------------------------------------------------------------------
+pub fn write(report : &Report, out : &mut dyn Write) -> io::Result<()> {
+
+    let config = report.config;
+
+    writeln!(out, "found {} files in {} ms", report.files.len(), report.elapsed.as_millis())?;
+    writeln!(out)?;
+
+    if config.verbose && !report.stages.is_empty() {
+        writeln!(out, "{:<10}{:>12}{:>12}{:>16}{:>12}", "stage", "files", "groups", "read MB", "split")?;
+        for st in &report.stages {
+            writeln!(out, "{:<10}{:>12}{:>12}{:>16.3}{:>12}", st.name, st.files, st.groups, to_mb(st.bytes_read), st.split)?;
+        }
+        writeln!(out)?;
+    }
+
+    if let Some(st) = report.stages.iter().find(|st| st.name == "bytes") {
+        writeln!(out, "byte verification split {} of the hash matched groups", st.split)?;
+        writeln!(out)?;
+    }
+
+    if report.roots.len() > 1 {
+        for (i, root) in report.roots.iter().enumerate() {
+            let kind = if root.reference {" (reference)"} else {""};
+            writeln!(out, "root #{}: {}{}", i, root.path.to_string_lossy(), kind)?;
+        }
+        writeln!(out)?;
+    }
+
+    writeln!(out, "total size for {} files is         {:.3} MB", report.files.len(), to_mb(report.total_size))?;
+    writeln!(out, "total size for duplicated files is {:.3} MB", to_mb(report.total_size_dups))?;
+    writeln!(out)?;
+
+    for (key, vsize, val) in &report.grouping {
+
+        if !config.verbose && val.len() < 2 || *vsize < config.size_filter {
+            continue;
+        }
+
+        writeln!(out, "\n{} * {}, totalSize: {:.3}", key, val.len(), to_mb(*vsize))?;
+        writeln!(out, "----------------------------------------")?;
+        for v in val {
+            if report.roots.len() > 1 {
+                let tag = if v.reference {"ref"} else {""};
+                writeln!(out, "{:6.3}   #{:<3}{:<3}   {}", to_mb(v.size), v.root, tag, v.path.to_string_lossy())?;
+            } else {
+                writeln!(out, "{:6.3}   {}", to_mb(v.size), v.path.to_string_lossy())?;
+            }
+        }
+    }
+
+    writeln!(out)
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::io;
+
+use rayon::prelude::*;
+
+use crate::errors::ErrorLog;
+use crate::hashing::{self, Digest, FileHasher};
+use crate::verify;
+use crate::walk::FileEntry;
+use crate::{FileEntryRVec, FileGroup};
+
+type DigestGroups<'a> = Vec<(u64, Digest, FileEntryRVec<'a>)>;
+
+//-------------------------------------------------------------------------------------------------
+//  how far candidates are confirmed after the partial hash stage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verify {
+    None,
+    Hash,
+    Bytes,
+}
+
+impl Verify {
+    pub fn name(self) -> &'static str {
+        match self {
+            Verify::None  => "none",
+            Verify::Hash  => "hash",
+            Verify::Bytes => "bytes",
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  how many files and groups survived a stage, how much had to be read to get there and how
+//  many of the incoming groups were broken up or lost members on the way
+pub struct StageStats {
+    pub name       : &'static str,
+    pub files      : usize,
+    pub groups     : usize,
+    pub bytes_read : u64,
+    pub split      : usize,
+}
+
+impl StageStats {
+    fn new(name : &'static str, groups : &DigestGroups<'_>, bytes_read : u64, split : usize) -> StageStats {
+        StageStats {
+            name,
+            files  : groups.iter().map(|g| g.2.len()).sum(),
+            groups : groups.len(),
+            bytes_read,
+            split,
+        }
+    }
+
+    fn add(&mut self, other : &StageStats) {
+        self.files      += other.files;
+        self.groups     += other.groups;
+        self.bytes_read += other.bytes_read;
+        self.split      += other.split;
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  splits one group of same-sized files by `digest_fn`, dropping members that end up alone
+//  and logging members that could not be read; members are hashed in parallel but keep their
+//  order
+fn split_group<'a, F>(members : FileEntryRVec<'a>, digest_fn : &F, log : &ErrorLog) -> Vec<(Digest, FileEntryRVec<'a>)>
+where
+    F : Fn(&FileEntry) -> io::Result<Digest> + Sync
+{
+    let digests : Vec<_> = members.par_iter()
+                            .map(|e| digest_fn(e))
+                            .collect();
+
+    let mapping : HashMap<Digest, FileEntryRVec<'a>>
+                = members.into_iter()
+                    .zip(digests)
+                    .filter_map(|(e, digest)| match digest {
+                        Ok(digest) => Some((digest, e)),
+                        Err(err)   => {
+                            log.record_io(&e.path, &err);
+                            None
+                        }
+                    })
+                    .fold(HashMap::new(), |mut acc, (k, x)| {
+                        acc.entry(k).or_default().push(x);
+                        acc
+                    });
+
+    let mut parts : Vec<_> = mapping.into_iter()
+                                .filter(|(_, v)| v.len() > 1)
+                                .collect();
+    parts.sort_by(|a, b| a.0.cmp(&b.0) );
+
+    parts
+}
+
+//-------------------------------------------------------------------------------------------------
+//  runs `split_fn` over every group in parallel, counting the groups that did not come out
+//  whole; the refined groups stay in the order of the groups they came from
+fn refine<'a, F>(groups : DigestGroups<'a>, split_fn : F) -> (DigestGroups<'a>, usize)
+where
+    F : Fn(u64, Digest, FileEntryRVec<'a>) -> Vec<(Digest, FileEntryRVec<'a>)> + Sync
+{
+    let results : Vec<_> = groups.into_par_iter()
+                            .map(|(size, digest, v)| {
+                                let count = v.len();
+                                let parts = split_fn(size, digest, v);
+                                let whole = parts.len() == 1 && parts[0].1.len() == count;
+                                (size, whole, parts)
+                            })
+                            .collect();
+
+    let mut split = 0;
+    let mut refined = vec![];
+
+    for (size, whole, parts) in results {
+        if !whole {
+            split += 1;
+        }
+        refined.extend(parts.into_iter().map(|(digest, v)| (size, digest, v)));
+    }
+
+    (refined, split)
+}
+
+//-------------------------------------------------------------------------------------------------
+fn size_stage(files : &[FileEntry]) -> DigestGroups<'_> {
+
+    let mapping : HashMap<u64, FileEntryRVec>
+                = files.iter()
+                    .fold(HashMap::new(), |mut acc, e| {
+                        acc.entry(e.size).or_default().push(e);
+                        acc
+                    });
+
+    //  no digest yet, every group of a size starts out with the same placeholder
+    let mut groups : DigestGroups
+                = mapping.into_iter()
+                    .filter(|(_, v)| v.len() > 1)
+                    .map(|(size, v)| (size, Digest::new(), v))
+                    .collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.0) );
+
+    groups
+}
+
+//-------------------------------------------------------------------------------------------------
+fn partial_stage<'a>(groups : DigestGroups<'a>, hasher : &FileHasher<'_>, log : &ErrorLog) -> (DigestGroups<'a>, usize) {
+
+    let digest_fn = |e : &FileEntry| hasher.partial(e);
+
+    refine(groups, |_, _, v| split_group(v, &digest_fn, log))
+}
+
+//-------------------------------------------------------------------------------------------------
+fn full_stage<'a>(groups : DigestGroups<'a>, hasher : &FileHasher<'_>, log : &ErrorLog) -> (DigestGroups<'a>, usize) {
+
+    let digest_fn = |e : &FileEntry| hasher.full(e);
+
+    refine(groups, |size, digest, v| {
+        //  the partial stage already read small files end to end
+        if size <= hasher.partial_size.saturating_mul(2) {
+            return vec![(digest, v)];
+        }
+        split_group(v, &digest_fn, log)
+    })
+}
+
+//-------------------------------------------------------------------------------------------------
+fn bytes_stage<'a>(groups : DigestGroups<'a>, log : &ErrorLog) -> (DigestGroups<'a>, usize) {
+
+    refine(groups, |size, digest, v| {
+        //  all empty files are trivially identical
+        if size == 0 {
+            return vec![(digest, v)];
+        }
+        verify::split_identical(v, log)
+            .into_iter()
+            .map(|class| (digest.clone(), class))
+            .collect()
+    })
+}
+
+//-------------------------------------------------------------------------------------------------
+fn bytes_to_read<F>(groups : &DigestGroups<'_>, read_fn : F) -> u64
+where
+    F : Fn(u64) -> u64
+{
+    groups.iter()
+        .map(|(size, _, v)| read_fn(*size) * v.len() as u64)
+        .sum()
+}
+
+//-------------------------------------------------------------------------------------------------
+//  runs every hashing/verification stage over one batch of size groups
+fn run_stages<'a>(by_size : DigestGroups<'a>, hasher : &FileHasher<'_>, verify : Verify, log : &ErrorLog) -> (DigestGroups<'a>, Vec<StageStats>) {
+
+    let mut stats = vec![];
+    let partial_size = hasher.partial_size;
+
+    let read = bytes_to_read(&by_size, |size| size.min(partial_size.saturating_mul(2)));
+    let (mut groups, split) = partial_stage(by_size, hasher, log);
+    stats.push(StageStats::new("partial", &groups, read, split));
+
+    if verify != Verify::None {
+        let read = bytes_to_read(&groups, |size| if size > partial_size.saturating_mul(2) {size} else {0});
+        let (by_full, split) = full_stage(groups, hasher, log);
+        stats.push(StageStats::new("full", &by_full, read, split));
+        groups = by_full;
+    }
+
+    if verify == Verify::Bytes {
+        let read = bytes_to_read(&groups, |size| size);
+        let (by_bytes, split) = bytes_stage(groups, log);
+        stats.push(StageStats::new("bytes", &by_bytes, read, split));
+        groups = by_bytes;
+    }
+
+    (groups, stats)
+}
+
+//-------------------------------------------------------------------------------------------------
+//  cuts the size groups into batches of roughly `batch_files` files, keeping their order
+fn into_batches(groups : DigestGroups<'_>, batch_files : usize) -> Vec<DigestGroups<'_>> {
+
+    let mut batches = vec![];
+    let mut batch : DigestGroups = vec![];
+    let mut files = 0;
+
+    for group in groups {
+        files += group.2.len();
+        batch.push(group);
+
+        if files >= batch_files {
+            batches.push(std::mem::take(&mut batch));
+            files = 0;
+        }
+    }
+
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+//-------------------------------------------------------------------------------------------------
+//  hands every group of identical files to `on_group` as soon as its batch is done, biggest
+//  files first, and returns the numbers of every stage summed over all batches
+pub fn get_content_grouping<'a, F>(files : &'a [FileEntry], hasher : &FileHasher<'_>, verify : Verify, log : &ErrorLog, mut on_group : F) -> Vec<StageStats>
+where
+    F : FnMut(FileGroup<'a>)
+{
+    //  FileEntryVec -> DigestGroups (size) -> (partial) -> (full) -> (bytes) -> FileGroup
+    //      each stage only looks at groups with more than one member, so by the time full hashes
+    //      are computed almost every file left over is expected to be a duplicate; size groups
+    //      go through the stages in batches big enough to keep every thread busy, so results
+    //      show up while the rest is still being hashed
+    let by_size = size_stage(files);
+    let mut stats = vec![StageStats::new("size", &by_size, 0, 0)];
+
+    //  an empty run gives the zeroed rows of the stages to sum into
+    stats.extend(run_stages(vec![], hasher, verify, log).1);
+
+    let batch_files = 64 * rayon::current_num_threads();
+
+    for batch in into_batches(by_size, batch_files) {
+        let (groups, batch_stats) = run_stages(batch, hasher, verify, log);
+
+        for (total, st) in stats[1..].iter_mut().zip(batch_stats) {
+            total.add(&st);
+        }
+
+        for (size, digest, v) in groups {
+            let vsize = size * v.len() as u64;
+            on_group((hashing::to_hex(&digest), vsize, v));
+        }
+    }
+
+    stats
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::hashing::Algorithm;
+
+    fn files(dir : &Path, contents : &[(&str, &[u8])]) -> Vec<FileEntry> {
+        contents.iter()
+            .map(|(name, data)| {
+                let path = dir.join(name);
+                fs::write(&path, data).unwrap();
+                FileEntry::new(path.clone(), &fs::metadata(&path).unwrap(), 0, false)
+            })
+            .collect()
+    }
+
+    //  the names in every group found, and the stats of the stage called `stage`
+    fn grouping(files : &[FileEntry], verify : Verify, stage : &str) -> (Vec<Vec<String>>, usize) {
+        let hasher = FileHasher { algorithm : Algorithm::Xxh3, partial_size : 4, cache : None };
+        let log = ErrorLog::new();
+        let mut groups = vec![];
+        let stats = get_content_grouping(files, &hasher, verify, &log, |(_, _, v)| {
+            groups.push(v.iter().map(|e| e.path.file_name().unwrap().to_string_lossy().into_owned()).collect());
+        });
+        let split = stats.iter().find(|st| st.name == stage).map(|st| st.split).unwrap_or(0);
+        (groups, split)
+    }
+
+    #[test]
+    fn a_different_middle_is_split_at_the_full_stage() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = files(dir.path(), &[("a", b"head-middle-1-tail"), ("b", b"head-middle-2-tail")]);
+
+        let (groups, split) = grouping(&files, Verify::Hash, "full");
+        assert!(groups.is_empty());
+        assert_eq!(split, 1);
+    }
+
+    #[test]
+    fn verify_none_trusts_the_partial_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = files(dir.path(), &[("a", b"head-middle-1-tail"), ("b", b"head-middle-2-tail"), ("c", b"head-middle-1-tail")]);
+
+        let (mut partial, _) = grouping(&files, Verify::None, "partial");
+        let (mut full, _) = grouping(&files, Verify::Hash, "full");
+        partial[0].sort();
+        full[0].sort();
+        assert_eq!(partial, vec![vec!["a", "b", "c"]]);
+        assert_eq!(full, vec![vec!["a", "c"]]);
+    }
+
+    #[test]
+    fn the_groups_come_in_the_same_order_on_any_number_of_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents : Vec<(String, Vec<u8>)> = (0..200).map(|i| (format!("f{:03}", i), vec![(i % 20) as u8; 100 + i % 40])).collect();
+        let contents : Vec<(&str, &[u8])> = contents.iter().map(|(name, data)| (name.as_str(), data.as_slice())).collect();
+        let files = files(dir.path(), &contents);
+
+        let grouped = |threads : usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| grouping(&files, Verify::Bytes, "bytes").0)
+        };
+
+        let single = grouped(1);
+        assert_eq!(single.len(), 40);
+        for threads in &[2, 8] {
+            assert_eq!(grouped(*threads), single);
+        }
+    }
+
+    //  a hash collision cannot be made to order, the group is handed over as if the digests matched
+    #[test]
+    fn same_digest_different_bytes_is_split_at_the_bytes_stage() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = files(dir.path(), &[("a", b"same size 1"), ("b", b"same size 2"), ("c", b"same size 1")]);
+        let log = ErrorLog::new();
+
+        let group = vec![(11, Digest::new(), files.iter().collect::<FileEntryRVec>())];
+        let (groups, split) = bytes_stage(group, &log);
+        assert_eq!(split, 1);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].2.iter().map(|e| e.path.as_path()).collect::<Vec<_>>(), vec![files[0].path.as_path(), files[2].path.as_path()]);
+    }
+}
@@ -0,0 +1,539 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::actions::journal::{self, Journal};
+use crate::actions::keep::KeepRule;
+use crate::actions::{self, script, GroupPlan, Method, Outcome};
+use crate::output::text::to_mb;
+use crate::output::{utc_timestamp, Report};
+use crate::walk::FileEntry;
+
+//  how much of a file the contents preview reads
+const PREVIEW_BYTES : usize = 4096;
+
+const HELP : &str = "j/k move  enter files  space mark  o keep only  u unmark  r apply rule  n next rule  p preview  e export  x execute  q quit";
+
+//-------------------------------------------------------------------------------------------------
+//  what is to happen to one file; undecided files are left alone like kept ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mark {
+    Undecided,
+    Keep,
+    Act,
+}
+
+//  one duplicate group with the marks made so far; `touched` groups are left alone by keep rules
+struct Group<'r> {
+    key     : &'r str,
+    size    : u64,
+    members : &'r [&'r FileEntry],
+    marks   : Vec<Mark>,
+    touched : bool,
+}
+
+impl Group<'_> {
+    fn wasted(&self) -> u64 {
+        self.size * (self.members.len() as u64 - 1)
+    }
+
+    //  files under reference roots are never acted on
+    fn reset(&mut self) {
+        self.marks = self.members.iter().map(|e| if e.reference {Mark::Keep} else {Mark::Undecided}).collect();
+        self.touched = false;
+    }
+
+    fn count(&self, mark : Mark) -> usize {
+        self.marks.iter().filter(|m| **m == mark).count()
+    }
+
+    //  what the marks do, files not marked to act on stay
+    fn plan(&self) -> GroupPlan<'_> {
+        let marked = |act| self.members.iter().zip(&self.marks).filter(|(_, m)| (**m == Mark::Act) == act).map(|(e, _)| *e).collect();
+        GroupPlan { key : self.key, size : self.size, keep : marked(false), act_on : marked(true), skipped : None }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Groups,
+    Files,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Preview {
+    Contents,
+    Metadata,
+}
+
+//  how the interface was left
+#[derive(Clone, Copy, PartialEq)]
+enum Decision {
+    Quit,
+    Execute,
+}
+
+//-------------------------------------------------------------------------------------------------
+//  raw mode on the alternate screen for as long as it lives, put back even on a panic
+struct Screen;
+
+impl Screen {
+    fn enter(mut out : &mut dyn Write) -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        execute!(&mut out, EnterAlternateScreen, Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  `text` cut to `width` characters, paths lose their start rather than their file name
+fn fit(text : &str, width : usize) -> String {
+    text.chars().take(width).collect()
+}
+
+//  control characters escaped, a file name must not be able to move the cursor or recolour the
+//  screen with escape sequences of its own
+fn printable(text : &str) -> String {
+    text.chars().map(|c| if c.is_control() {c.escape_default().to_string()} else {c.to_string()}).collect()
+}
+
+fn fit_path(path : &str, width : usize) -> String {
+    let len = path.chars().count();
+    if len <= width || width < 2 {
+        return fit(path, width);
+    }
+    let tail : String = path.chars().skip(len - width + 1).collect();
+    format!("…{}", tail)
+}
+
+//  the start of a file as text lines when it looks like text, as a hex dump otherwise
+fn contents(e : &FileEntry) -> Vec<String> {
+    let mut head = vec![];
+    if let Err(err) = File::open(&e.path).and_then(|f| f.take(PREVIEW_BYTES as u64).read_to_end(&mut head)) {
+        return vec![format!("cannot read: {}", err)];
+    }
+
+    //  a character cut in half at the end of the preview still counts as text
+    let text = match std::str::from_utf8(&head) {
+        Ok(text)                                 => Some(text),
+        Err(err) if err.error_len().is_none()    => std::str::from_utf8(&head[..err.valid_up_to()]).ok(),
+        Err(_)                                   => None,
+    };
+
+    match text.filter(|t| !t.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))) {
+        Some(text) => text.lines().map(|l| l.replace('\t', "    ")).collect(),
+        None       => head.chunks(16)
+                        .enumerate()
+                        .map(|(i, chunk)| {
+                            let hex : Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                            let ascii : String = chunk.iter()
+                                                    .map(|&b| if b.is_ascii_graphic() || b == b' ' {b as char} else {'.'})
+                                                    .collect();
+                            format!("{:08x}  {:<48}  {}", i * 16, hex.join(" "), ascii)
+                        })
+                        .collect(),
+    }
+}
+
+//  what the scan knows about a file and what the filesystem says now
+fn metadata(report : &Report, key : &str, e : &FileEntry) -> Vec<String> {
+    let mut lines = vec![
+        format!("path        {}", e.path.to_string_lossy()),
+        format!("size        {} bytes ({:.3} MB)", e.size, to_mb(e.size)),
+        format!("modified    {} UTC", utc_timestamp(e.mtime)),
+        format!("device      {}, inode {}", e.dev, e.ino),
+        format!("root        {}{}", report.roots[e.root].path.to_string_lossy(), if e.reference {" (reference)"} else {""}),
+        format!("hash        {}", key),
+    ];
+
+    match fs::symlink_metadata(&e.path) {
+        Ok(md) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                lines.push(format!("links       {}", md.nlink()));
+                lines.push(format!("mode        {:o}", md.mode() & 0o7777));
+            }
+            if md.len() != e.size {
+                lines.push(format!("changed     now {} bytes", md.len()));
+            }
+        }
+        Err(err) => lines.push(format!("gone        {}", err)),
+    }
+    lines
+}
+
+//-------------------------------------------------------------------------------------------------
+struct App<'r> {
+    groups  : Vec<Group<'r>>,
+    group   : usize,
+    file    : usize,
+    top     : usize,
+    focus   : Focus,
+    preview : Preview,
+    rules   : Vec<KeepRule>,
+    rule    : usize,
+    status  : String,
+    pending : Option<Decision>,
+    saved   : bool,
+}
+
+impl<'r> App<'r> {
+    fn new(report : &'r Report) -> App<'r> {
+
+        let mut groups : Vec<Group> = report.duplicate_groups()
+                                        .map(|(key, _, val)| {
+                                            let mut group = Group { key, size : val[0].size, members : val, marks : vec![], touched : false };
+                                            group.reset();
+                                            group
+                                        })
+                                        .collect();
+        groups.sort_by_key(|g| std::cmp::Reverse(g.wasted()));
+
+        //  --keep comes first, path-matches needs a pattern and is only there when given
+        let keep = report.config.keep.clone();
+        let mut rules = vec![];
+        for name in KeepRule::NAMES.iter().filter(|n| !n.contains('=') && **n != keep.name()) {
+            if let Ok(rule) = KeepRule::from_name(name) {
+                rules.push(rule);
+            }
+        }
+        rules.insert(0, keep);
+
+        App {
+            groups,
+            group   : 0,
+            file    : 0,
+            top     : 0,
+            focus   : Focus::Groups,
+            preview : Preview::Metadata,
+            rules,
+            rule    : 0,
+            status  : HELP.to_string(),
+            pending : None,
+            saved   : true,
+        }
+    }
+
+    //  the groups with files marked to act on, split the way they were marked
+    fn plans(&self) -> Vec<GroupPlan<'_>> {
+        self.groups.iter()
+            .filter(|g| g.count(Mark::Act) > 0)
+            .map(Group::plan)
+            .collect()
+    }
+
+    fn marked_bytes(&self) -> (usize, u64) {
+        self.groups.iter()
+            .map(|g| (g.count(Mark::Act), g.count(Mark::Act) as u64 * g.size))
+            .fold((0, 0), |(n, b), (gn, gb)| (n + gn, b + gb))
+    }
+
+    fn move_by(&mut self, delta : isize) {
+        let (pos, len) = match self.focus {
+            Focus::Groups => (&mut self.group, self.groups.len()),
+            Focus::Files  => (&mut self.file, self.groups[self.group].members.len()),
+        };
+        *pos = (*pos as isize + delta).clamp(0, len as isize - 1) as usize;
+        if self.focus == Focus::Groups {
+            self.file = 0;
+        }
+    }
+
+    //  keep <-> act on the selected file, never leaving a group without a kept file
+    fn toggle(&mut self) {
+        let group = &mut self.groups[self.group];
+        let file = self.file;
+
+        if group.members[file].reference {
+            self.status = "files under --reference roots are always kept".to_string();
+            return;
+        }
+        let mark = match group.marks[file] {
+            Mark::Act => Mark::Keep,
+            _         => Mark::Act,
+        };
+        if mark == Mark::Act && group.count(Mark::Act) + 1 == group.members.len() {
+            self.status = "every group keeps at least one file".to_string();
+            return;
+        }
+        group.marks[file] = mark;
+        group.touched = true;
+        self.saved = false;
+    }
+
+    //  the selected file stays, all others but reference files go
+    fn keep_only(&mut self) {
+        let group = &mut self.groups[self.group];
+        let file = self.file;
+        for (i, (e, mark)) in group.members.iter().zip(group.marks.iter_mut()).enumerate() {
+            *mark = if i == file || e.reference {Mark::Keep} else {Mark::Act};
+        }
+        group.touched = true;
+        self.saved = false;
+    }
+
+    //  the current rule decides every group nobody has marked yet
+    fn apply_rule(&mut self) {
+        let rule = &self.rules[self.rule];
+        let mut applied = 0;
+        let mut left = 0;
+
+        for group in self.groups.iter_mut().filter(|g| !g.touched) {
+            match rule.keep_flags(group.members) {
+                Some(flags) => {
+                    group.marks = flags.into_iter().map(|keep| if keep {Mark::Keep} else {Mark::Act}).collect();
+                    group.touched = true;
+                    applied += 1;
+                }
+                None        => left += 1,
+            }
+        }
+        if applied > 0 {
+            self.saved = false;
+        }
+        self.status = format!("--keep {} decided {} groups, {} had no file to keep by it", rule.name(), applied, left);
+    }
+
+    //  writes the marks as a script next to where the scan was started
+    fn export(&mut self, report : &Report, method : &Method) {
+        let path = format!("lsdups-decisions-{}.sh", journal::stamp());
+        let written = File::create(&path)
+                        .map(io::BufWriter::new)
+                        .and_then(|mut file| script::write_plan(report, self.plans(), method, "marks made in lsdups-rust tui", &mut file));
+        self.status = match written {
+            Ok(outcome) => {
+                self.saved = true;
+                format!("wrote {}, {} files, {} skipped", path, outcome.files, outcome.skipped)
+            }
+            Err(err)    => format!("failed to write {}: {}", path, err),
+        };
+    }
+
+    fn on_key(&mut self, key : KeyEvent, report : &Report, method : &Method) -> Option<Decision> {
+
+        if let Some(decision) = self.pending.take() {
+            if key.code == KeyCode::Char('y') {
+                return Some(decision);
+            }
+            self.status = HELP.to_string();
+            return None;
+        }
+
+        let (verb, _) = method.verbs();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(Decision::Quit),
+            KeyCode::Up | KeyCode::Char('k')                     => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j')                   => self.move_by(1),
+            KeyCode::PageUp                                      => self.move_by(-10),
+            KeyCode::PageDown                                    => self.move_by(10),
+            KeyCode::Home                                        => self.move_by(isize::MIN / 2),
+            KeyCode::End                                         => self.move_by(isize::MAX / 2),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.focus = Focus::Files,
+            KeyCode::Left | KeyCode::Char('h')                   => self.focus = Focus::Groups,
+            KeyCode::Esc if self.focus == Focus::Files           => self.focus = Focus::Groups,
+            KeyCode::Tab                                         => self.focus = if self.focus == Focus::Groups {Focus::Files} else {Focus::Groups},
+            KeyCode::Char(' ') if self.focus == Focus::Files     => self.toggle(),
+            KeyCode::Char('o') if self.focus == Focus::Files     => self.keep_only(),
+            KeyCode::Char(' ') | KeyCode::Char('o')              => self.status = "open the group with enter to mark its files".to_string(),
+            KeyCode::Char('u')                                   => {
+                self.groups[self.group].reset();
+                self.saved = false;
+            }
+            KeyCode::Char('r')                                   => self.apply_rule(),
+            KeyCode::Char('n')                                   => {
+                self.rule = (self.rule + 1) % self.rules.len();
+                self.status = format!("r applies --keep {} to the groups not marked yet", self.rules[self.rule].name());
+            }
+            KeyCode::Char('p')                                   => self.preview = if self.preview == Preview::Contents {Preview::Metadata} else {Preview::Contents},
+            KeyCode::Char('e')                                   => self.export(report, method),
+            KeyCode::Char('x')                                   => {
+                let (files, bytes) = self.marked_bytes();
+                if files == 0 {
+                    self.status = format!("no file marked to {}", verb);
+                }
+                else {
+                    self.status = format!("{} {} files ({:.3} MB) now? y to go ahead", verb, files, to_mb(bytes));
+                    self.pending = Some(Decision::Execute);
+                }
+            }
+            KeyCode::Char('q') | KeyCode::Esc                    => {
+                if self.saved {
+                    return Some(Decision::Quit);
+                }
+                self.status = "quit without executing or exporting the marks? y to quit".to_string();
+                self.pending = Some(Decision::Quit);
+            }
+            _                                                    => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, report : &Report, method : &Method, mut out : &mut dyn Write) -> io::Result<()> {
+
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        let height = (height as usize).max(12);
+
+        let group_rows = (height - 4) * 2 / 5;
+        let file_rows = (height - 4) / 4;
+        let preview_rows = height - 4 - group_rows - file_rows;
+
+        let (verb, _) = method.verbs();
+        let wasted : u64 = self.groups.iter().map(|g| g.wasted()).sum();
+        let (files, bytes) = self.marked_bytes();
+
+        let mut lines : Vec<(String, bool)> = vec![];
+        lines.push((format!("lsdups-rust tui  {} groups, {:.3} MB wasted, {} files marked to {} ({:.3} MB), rule: {}",
+                            self.groups.len(), to_mb(wasted), files, verb, to_mb(bytes), self.rules[self.rule].name()), true));
+
+        //  the group list scrolls to keep the selection in view
+        if self.group < self.top {
+            self.top = self.group;
+        }
+        if self.group >= self.top + group_rows {
+            self.top = self.group + 1 - group_rows;
+        }
+        for (i, g) in self.groups.iter().enumerate().skip(self.top).take(group_rows) {
+            let state = match (g.count(Mark::Act), g.count(Mark::Keep)) {
+                (0, _) if g.touched => "kept".to_string(),
+                (0, _)              => "".to_string(),
+                (n, 0)              => format!("{} to {}, the rest kept", n, verb),
+                (n, _)              => format!("{} to {}", n, verb),
+            };
+            let text = format!("{} {:>12.3} MB wasted  {:>3} x {:>10.3} MB  {}  {}",
+                                if i == self.group {">"} else {" "}, to_mb(g.wasted()), g.members.len(), to_mb(g.size), g.key, state);
+            lines.push((text, i == self.group && self.focus == Focus::Groups));
+        }
+        lines.resize(1 + group_rows, (String::new(), false));
+
+        let group = &self.groups[self.group];
+        lines.push((format!("-- group {}/{}, {} --", self.group + 1, self.groups.len(), group.key), true));
+
+        let file_top = self.file.saturating_sub(file_rows.saturating_sub(1));
+        for (i, (e, mark)) in group.members.iter().zip(&group.marks).enumerate().skip(file_top).take(file_rows) {
+            let mark = match mark {
+                Mark::Keep if e.reference => "keep ref",
+                Mark::Keep                => "keep",
+                Mark::Act                 => verb,
+                Mark::Undecided           => "",
+            };
+            let text = format!("{} {:<9}{}", if i == self.file {">"} else {" "}, mark, fit_path(&e.path.to_string_lossy(), width.saturating_sub(11)));
+            lines.push((text, i == self.file && self.focus == Focus::Files));
+        }
+        lines.resize(2 + group_rows + file_rows, (String::new(), false));
+
+        let e = group.members[self.file];
+        let (title, body) = match self.preview {
+            Preview::Contents => ("contents", contents(e)),
+            Preview::Metadata => ("metadata", metadata(report, group.key, e)),
+        };
+        lines.push((format!("-- {} of {} (p for {}) --", title, e.file_name().to_string_lossy(),
+                            if self.preview == Preview::Contents {"metadata"} else {"contents"}), true));
+        lines.extend(body.into_iter().take(preview_rows).map(|l| (l, false)));
+        lines.resize(height - 1, (String::new(), false));
+        lines.push((self.status.clone(), self.pending.is_some()));
+
+        queue!(&mut out, Clear(ClearType::All))?;
+        for (row, (text, highlight)) in lines.iter().enumerate() {
+            queue!(&mut out, MoveTo(0, row as u16))?;
+            if *highlight {
+                queue!(&mut out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(&mut out, Print(fit(&printable(text), width)))?;
+            if *highlight {
+                queue!(&mut out, SetAttribute(Attribute::Reset))?;
+            }
+        }
+        out.flush()
+    }
+
+    fn interact(&mut self, report : &Report, method : &Method, out : &mut dyn Write) -> io::Result<Decision> {
+        loop {
+            self.draw(report, method, out)?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(decision) = self.on_key(key, report, method) {
+                    return Ok(decision);
+                }
+            }
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  lets the groups be gone through and marked by hand, then carries out `method` on the files
+//  marked for it with every change journaled like --execute, or just quits; stdout has to be a
+//  terminal
+pub fn run(report : &Report, method : &Method, out : &mut dyn Write) -> io::Result<Outcome> {
+
+    let mut app = App::new(report);
+    if app.groups.is_empty() {
+        writeln!(out, "no duplicates found")?;
+        return Ok(Outcome::default());
+    }
+
+    let decision = {
+        let _screen = Screen::enter(out)?;
+        app.interact(report, method, out)?
+    };
+
+    if decision == Decision::Quit {
+        return Ok(Outcome::default());
+    }
+
+    let path = report.config.journal.clone().unwrap_or_else(journal::default_path);
+    let mut journal = Journal::open(&path, report.config.algorithm)
+                        .map_err(|err| io::Error::new(err.kind(), format!("failed to open journal {}: {}", path.to_string_lossy(), err)))?;
+
+    let outcome = actions::run_plan(report, app.plans(), method, Some(&mut journal), out);
+    eprintln!("changes recorded in {}", path.to_string_lossy());
+    outcome
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn entry(path : &str, reference : bool) -> FileEntry {
+        FileEntry { path : PathBuf::from(path), size : 3, mtime : 0, mtime_nsec : 0, dev : 1, ino : path.len() as u64, root : 0, reference }
+    }
+
+    #[test]
+    fn undecided_files_are_kept() {
+        let (a, b, c) = (entry("/a", false), entry("/bb", false), entry("/ccc", true));
+        let members = [&a, &b, &c];
+        let mut group = Group { key : "k", size : 3, members : &members, marks : vec![], touched : false };
+        group.reset();
+        group.marks[1] = Mark::Act;
+
+        let plan = group.plan();
+        let paths = |files : &[&FileEntry]| files.iter().map(|e| e.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&plan.act_on), vec![PathBuf::from("/bb")]);
+        assert_eq!(paths(&plan.keep), vec![PathBuf::from("/a"), PathBuf::from("/ccc")]);
+        assert!(plan.skipped.is_none());
+    }
+
+    #[test]
+    fn escape_sequences_are_not_printed() {
+        assert_eq!(printable("/tmp/a\u{1b}]0;pwned\u{7}b\n"), "/tmp/a\\u{1b}]0;pwned\\u{7}b\\n");
+        assert_eq!(printable("/tmp/\u{9b}31m"), "/tmp/\\u{9b}31m");
+        assert_eq!(printable("/tmp/é …"), "/tmp/é …");
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::errors::ErrorLog;
+use crate::FileEntryRVec;
+
+//-------------------------------------------------------------------------------------------------
+fn map_file(path : &Path) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    //  SAFETY: the map is only read while comparing; a file truncated underneath us by another
+    //  process is the one case this cannot guard against, same as any other mmap based tool
+    unsafe { Mmap::map(&file) }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  compares every member of a group of equally sized files byte by byte and splits it into
+//  classes of truly identical files, dropping classes with a single member
+pub fn split_identical<'a>(members : FileEntryRVec<'a>, log : &ErrorLog) -> Vec<FileEntryRVec<'a>> {
+
+    //  each class keeps the mapping of its first member around as the one to compare against
+    let mut classes : Vec<(Mmap, FileEntryRVec)> = vec![];
+
+    for e in members {
+        let map = match map_file(&e.path) {
+            Ok(map) => map,
+            Err(err) => {
+                log.record_io(&e.path, &err);
+                continue;
+            }
+        };
+
+        match classes.iter_mut().find(|(rep, _)| rep[..] == map[..]) {
+            Some((_, class)) => class.push(e),
+            None             => classes.push((map, vec![e])),
+        }
+    }
+
+    classes.into_iter()
+        .map(|(_, class)| class)
+        .filter(|class| class.len() > 1)
+        .collect()
+}
@@ -0,0 +1,406 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::errors::{ErrorLog, ScanError};
+
+//-------------------------------------------------------------------------------------------------
+//  a regular file found by the walk, with the metadata the grouping needs looked up once
+pub struct FileEntry {
+    pub path       : PathBuf,
+    pub size       : u64,
+    pub mtime      : i64,
+    pub mtime_nsec : u32,
+    pub dev        : u64,
+    pub ino        : u64,
+    pub root       : usize,
+    pub reference  : bool,
+}
+
+impl FileEntry {
+    #[cfg(unix)]
+    pub fn new(path : PathBuf, md : &fs::Metadata, root : usize, reference : bool) -> FileEntry {
+        use std::os::unix::fs::MetadataExt;
+        FileEntry {
+            path,
+            size       : md.len(),
+            mtime      : md.mtime(),
+            mtime_nsec : md.mtime_nsec() as u32,
+            dev        : md.dev(),
+            ino        : md.ino(),
+            root,
+            reference,
+        }
+    }
+
+    //  no inode numbers to go by, device and inode stay 0
+    #[cfg(not(unix))]
+    pub fn new(path : PathBuf, md : &fs::Metadata, root : usize, reference : bool) -> FileEntry {
+        let since_epoch = md.modified()
+                            .ok()
+                            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                            .unwrap_or_default();
+        FileEntry {
+            path,
+            size       : md.len(),
+            mtime      : since_epoch.as_secs() as i64,
+            mtime_nsec : since_epoch.subsec_nanos(),
+            dev        : 0,
+            ino        : 0,
+            root,
+            reference,
+        }
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  a directory to scan; files under reference roots are only ever matched against, never
+//  reported as the duplicates to get rid of
+#[derive(Clone)]
+pub struct Root {
+    pub path      : PathBuf,
+    pub reference : bool,
+}
+
+//  (device, inode) of a directory, used to notice when a bind mount leads back to an ancestor
+type DirId = (u64, u64);
+
+//  the directories above the one being visited, shared between all of its subdirectory tasks
+struct Ancestor {
+    path   : PathBuf,
+    id     : DirId,
+    parent : Ancestors,
+}
+
+type Ancestors = Option<Arc<Ancestor>>;
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(unix)]
+fn dir_id(md : &fs::Metadata) -> Option<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((md.dev(), md.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(_md : &fs::Metadata) -> Option<DirId> {
+    None
+}
+
+//-------------------------------------------------------------------------------------------------
+fn find_ancestor(ancestors : &Ancestors, id : DirId) -> Option<&Path> {
+    let mut next = ancestors;
+    while let Some(ancestor) = next {
+        if ancestor.id == id {
+            return Some(&ancestor.path);
+        }
+        next = &ancestor.parent;
+    }
+    None
+}
+
+//-------------------------------------------------------------------------------------------------
+//  a walk shared by every task on the pool
+struct Walk<'s, F> {
+    root      : usize,
+    reference : bool,
+    root_ids  : &'s [DirId],
+    accept    : &'s F,
+    found  : Mutex<Vec<FileEntry>>,
+    log    : &'s ErrorLog,
+}
+
+impl<'s, F> Walk<'s, F> {
+    fn entry(&self, path : PathBuf, md : &fs::Metadata) -> FileEntry {
+        FileEntry::new(path, md, self.root, self.reference)
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  reads one directory, hands every subdirectory to the pool as its own task and collects the
+//  accepted files in one go to keep contention on `found` low
+fn visit<'s, F>(scope : &rayon::Scope<'s>, walk : &'s Walk<'s, F>, dir : PathBuf, ancestors : Ancestors)
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err)    => return walk.log.record_io(&dir, &err),
+    };
+
+    let mut local = vec![];
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err)  => {
+                walk.log.record_io(&dir, &err);
+                continue;
+            }
+        };
+
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(t)    => t,
+            Err(err) => {
+                walk.log.record_io(&path, &err);
+                continue;
+            }
+        };
+
+        if file_type.is_dir() {
+            let ancestors = match entry.metadata().map(|md| dir_id(&md)) {
+                Ok(Some(id)) => {
+                    //  another root nested in this one, it gets walked on its own
+                    if walk.root_ids.contains(&id) {
+                        continue;
+                    }
+                    if let Some(ancestor) = find_ancestor(&ancestors, id) {
+                        walk.log.record(ScanError::new_loop(&path, ancestor));
+                        continue;
+                    }
+                    Some(Arc::new(Ancestor { path : path.clone(), id, parent : ancestors.clone() }))
+                }
+                Ok(None) => ancestors.clone(),
+                Err(err) => {
+                    walk.log.record_io(&path, &err);
+                    continue;
+                }
+            };
+            scope.spawn(move |s| visit(s, walk, path, ancestors));
+        }
+        else if file_type.is_file() && (walk.accept)(&entry.file_name()) {
+            match entry.metadata() {
+                Ok(md)   => local.push(walk.entry(path, &md)),
+                Err(err) => walk.log.record_io(&path, &err),
+            }
+        }
+    }
+
+    walk.found.lock().unwrap().extend(local);
+}
+
+//-------------------------------------------------------------------------------------------------
+//  drops roots that are given twice or that live inside another root of the same kind, so
+//  nothing gets walked twice; a directory given both ways stays a reference root. Roots are
+//  the same directory when their paths resolve alike or when they have the same device and
+//  inode, which catches bind mounts. Returns the roots to walk in the order given and each
+//  dropped root with the root covering it
+pub fn dedupe_roots(roots : Vec<Root>) -> (Vec<Root>, Vec<(PathBuf, PathBuf)>) {
+
+    //  roots that cannot be resolved are kept as given, the walk will report them
+    let resolved : Vec<PathBuf> = roots.iter()
+                                    .map(|r| fs::canonicalize(&r.path).unwrap_or_else(|_| r.path.clone()))
+                                    .collect();
+
+    let ids : Vec<Option<DirId>> = roots.iter()
+                                    .map(|r| fs::metadata(&r.path).ok().and_then(|md| dir_id(&md)))
+                                    .collect();
+
+    //  the directories each root is in, to notice one reached through another path
+    let above : Vec<Vec<DirId>> = resolved.iter()
+                                    .map(|path| path.ancestors()
+                                                    .skip(1)
+                                                    .filter_map(|dir| fs::metadata(dir).ok())
+                                                    .filter_map(|md| dir_id(&md))
+                                                    .collect())
+                                    .collect();
+
+    let same = |i : usize, j : usize| resolved[i] == resolved[j] || (ids[i].is_some() && ids[i] == ids[j]);
+    let inside = |i : usize, j : usize| {
+        !same(i, j) && (resolved[i].starts_with(&resolved[j]) || ids[j].is_some_and(|id| above[i].contains(&id)))
+    };
+
+    let mut covered_by : Vec<Option<usize>> = vec![None; roots.len()];
+
+    //  repeats first, so nesting is only ever checked against roots that are going to be walked
+    for i in 0..roots.len() {
+        covered_by[i] = (0..roots.len()).find(|&j| {
+            let prefer_other = if roots[i].reference != roots[j].reference {roots[j].reference} else {j < i};
+            j != i && same(i, j) && prefer_other
+        });
+    }
+
+    for i in 0..roots.len() {
+        if covered_by[i].is_some() {
+            continue;
+        }
+        covered_by[i] = (0..roots.len()).find(|&j| {
+            covered_by[j].is_none()
+                && inside(i, j)
+                && roots[i].reference == roots[j].reference
+        });
+    }
+
+    let dropped = covered_by.iter()
+                    .enumerate()
+                    .filter_map(|(i, j)| j.map(|j| (roots[i].path.clone(), roots[j].path.clone())))
+                    .collect();
+
+    let kept = roots.into_iter()
+                .zip(covered_by)
+                .filter_map(|(root, j)| if j.is_none() {Some(root)} else {None})
+                .collect();
+
+    (kept, dropped)
+}
+
+//-------------------------------------------------------------------------------------------------
+fn walk_root<'s, F>(scope : &rayon::Scope<'s>, walk : &'s Walk<'s, F>, root : &Path)
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    match fs::metadata(root) {
+        Ok(md) if md.is_file() => {
+            if (walk.accept)(root.file_name().unwrap_or_default()) {
+                walk.found.lock().unwrap().push(walk.entry(root.to_path_buf(), &md));
+            }
+        }
+        Ok(md) => {
+            let ancestors = dir_id(&md).map(|id| Arc::new(Ancestor { path : root.to_path_buf(), id, parent : None }));
+            let root = root.to_path_buf();
+            scope.spawn(move |s| visit(s, walk, root, ancestors));
+        }
+        Err(err) => walk.log.record_io(root, &err),
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  walks all `roots` on the rayon pool, symlinks are not followed and anything that cannot be
+//  read ends up in `log`; every file remembers the index of the root it was found under, roots
+//  nested in another root are left to their own walk, and
+//  the result is sorted bigger files first and by path after that, so it does not depend on
+//  how the threads were scheduled
+pub fn walk<F>(roots : &[Root], accept : &F, log : &ErrorLog) -> Vec<FileEntry>
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    let root_ids : Vec<DirId> = roots.iter()
+                                .filter_map(|r| fs::metadata(&r.path).ok())
+                                .filter_map(|md| dir_id(&md))
+                                .collect();
+
+    let walks : Vec<Walk<F>> = roots.iter()
+                                .enumerate()
+                                .map(|(root, r)| Walk {
+                                    root,
+                                    reference : r.reference,
+                                    root_ids  : &root_ids,
+                                    accept,
+                                    found     : Mutex::new(vec![]),
+                                    log
+                                })
+                                .collect();
+
+    rayon::scope(|s| {
+        for (walk, root) in walks.iter().zip(roots) {
+            walk_root(s, walk, &root.path);
+        }
+    });
+
+    let mut files : Vec<FileEntry> = walks.into_iter()
+                                        .flat_map(|walk| walk.found.into_inner().unwrap())
+                                        .collect();
+    files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)) );
+
+    files
+}
+
+//-------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(path : &Path, reference : bool) -> Root {
+        Root { path : path.to_path_buf(), reference }
+    }
+
+    fn paths(roots : &[Root]) -> Vec<&Path> {
+        roots.iter().map(|r| r.path.as_path()).collect()
+    }
+
+    //  a few levels of directories, files of some sizes repeated across them
+    fn tree(dir : &Path) {
+        for i in 0..6 {
+            let sub = dir.join(format!("d{}", i)).join(format!("e{}", i % 3));
+            fs::create_dir_all(&sub).unwrap();
+            for j in 0..8 {
+                fs::write(sub.join(format!("f{}", j)), vec![b'x'; (j % 4) * 100 + i % 2]).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn the_order_does_not_depend_on_the_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        tree(dir.path());
+        let roots = vec![root(dir.path(), false)];
+
+        let walked = |threads : usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let files = pool.install(|| walk(&roots, &|_ : &OsStr| true, &ErrorLog::new()));
+            files.into_iter().map(|e| e.path).collect::<Vec<_>>()
+        };
+
+        let single = walked(1);
+        assert_eq!(single.len(), 48);
+        for threads in &[2, 8] {
+            assert_eq!(walked(*threads), single);
+        }
+    }
+
+    #[test]
+    fn repeated_roots_are_walked_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        fs::create_dir(&a).unwrap();
+
+        let (kept, dropped) = dedupe_roots(vec![root(&a, false), root(&a.join("."), false)]);
+        assert_eq!(paths(&kept), vec![a.as_path()]);
+        assert_eq!(dropped, vec![(a.join("."), a.clone())]);
+    }
+
+    #[test]
+    fn nested_roots_of_the_same_kind_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let sub = a.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+
+        let (kept, dropped) = dedupe_roots(vec![root(&sub, false), root(&a, false)]);
+        assert_eq!(paths(&kept), vec![a.as_path()]);
+        assert_eq!(dropped, vec![(sub.clone(), a.clone())]);
+
+        //  a reference root inside a normal one is walked on its own
+        let (kept, dropped) = dedupe_roots(vec![root(&a, false), root(&sub, true)]);
+        assert_eq!(paths(&kept), vec![a.as_path(), sub.as_path()]);
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn a_root_given_both_ways_stays_a_reference() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let (kept, _) = dedupe_roots(vec![root(dir.path(), false), root(dir.path(), true)]);
+        assert_eq!(kept.len(), 1);
+        assert!(kept[0].reference);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_directory_reached_through_a_symlink_is_the_same_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let link = dir.path().join("link");
+        fs::create_dir_all(a.join("sub")).unwrap();
+        std::os::unix::fs::symlink(&a, &link).unwrap();
+
+        let (kept, dropped) = dedupe_roots(vec![root(&a, false), root(&link, false), root(&link.join("sub"), false)]);
+        assert_eq!(paths(&kept), vec![a.as_path()]);
+        assert_eq!(dropped.len(), 2);
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::errors::{ErrorLog, ErrorPolicy};
+use crate::hashing::{self, Digest, FileHasher};
+use crate::output::json::{Error, Group, RawPath};
+use crate::pipeline::Verify;
+use crate::verify;
+use crate::walk::{self, FileEntry, Root};
+use crate::Config;
+
+const EVENT_BUFFER_SIZE : usize = 64 * 1024;
+
+//-------------------------------------------------------------------------------------------------
+//  an incoming file with the same content as files already there, the new file comes first
+#[derive(Serialize)]
+struct DuplicateEvent<'a> {
+    #[serde(rename = "type")]
+    kind  : &'static str,
+    path  : RawPath,
+    #[serde(flatten)]
+    group : Group<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorEvent {
+    #[serde(rename = "type")]
+    kind  : &'static str,
+    #[serde(flatten)]
+    error : Error,
+}
+
+//-------------------------------------------------------------------------------------------------
+fn emit<T : Serialize>(record : &T, out : &mut dyn Write) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+//-------------------------------------------------------------------------------------------------
+//  files are only looked at once they were closed after writing or moved in, never half written
+fn watch_mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE | WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO
+        | WatchMask::DONT_FOLLOW | WatchMask::ONLYDIR
+}
+
+//-------------------------------------------------------------------------------------------------
+//  every file under the watched roots by size, digests are only computed once a file of the
+//  same size turns up and kept until the file changes
+struct Index<'c> {
+    hasher  : FileHasher<'c>,
+    verify  : Verify,
+    files   : HashMap<PathBuf, FileEntry>,
+    by_size : HashMap<u64, BTreeSet<PathBuf>>,
+    digests : HashMap<PathBuf, Digest>,
+}
+
+impl Index<'_> {
+    fn insert(&mut self, e : FileEntry) {
+        self.remove(&e.path);
+        self.by_size.entry(e.size).or_default().insert(e.path.clone());
+        self.files.insert(e.path.clone(), e);
+    }
+
+    fn remove(&mut self, path : &Path) {
+        let e = match self.files.remove(path) {
+            Some(e) => e,
+            None    => return,
+        };
+        self.digests.remove(path);
+        if let Some(paths) = self.by_size.get_mut(&e.size) {
+            paths.remove(path);
+            if paths.is_empty() {
+                self.by_size.remove(&e.size);
+            }
+        }
+    }
+
+    fn remove_tree(&mut self, dir : &Path) {
+        let paths : Vec<PathBuf> = self.files.keys()
+                                    .filter(|p| p.starts_with(dir))
+                                    .cloned()
+                                    .collect();
+        for path in paths {
+            self.remove(&path);
+        }
+    }
+
+    //  the same digest the scan would group by at this --verify level
+    fn digest(&mut self, path : &Path) -> io::Result<Digest> {
+        if let Some(digest) = self.digests.get(path) {
+            return Ok(digest.clone());
+        }
+
+        let e = &self.files[path];
+        let digest = match self.verify {
+            Verify::None => self.hasher.partial(e),
+            _            => self.hasher.full(e),
+        }?;
+
+        self.digests.insert(path.to_path_buf(), digest.clone());
+        Ok(digest)
+    }
+
+    //  the other indexed files with the same content as the indexed file at `path`; empty files
+    //  are all alike and never reported
+    fn matches(&mut self, path : &Path, log : &ErrorLog) -> io::Result<Option<(Digest, Vec<PathBuf>)>> {
+
+        let size = self.files[path].size;
+        let candidates : Vec<PathBuf> = self.by_size[&size].iter()
+                                            .filter(|p| *p != path)
+                                            .cloned()
+                                            .collect();
+        if size == 0 || candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let digest = self.digest(path)?;
+        let mut same = vec![];
+
+        for candidate in candidates {
+            match self.digest(&candidate) {
+                Ok(d) if d == digest => same.push(candidate),
+                Ok(_)                => {}
+                Err(err)             => log.record_io(&candidate, &err),
+            }
+        }
+
+        if self.verify == Verify::Bytes && !same.is_empty() {
+            let new = &self.files[path];
+            let members : Vec<&FileEntry> = iter::once(path)
+                                                .chain(same.iter().map(PathBuf::as_path))
+                                                .map(|p| &self.files[p])
+                                                .collect();
+
+            same = verify::split_identical(members, log)
+                    .into_iter()
+                    .find(|class| class.iter().any(|e| std::ptr::eq(*e, new)))
+                    .map(|class| class.into_iter().filter(|e| !std::ptr::eq(*e, new)).map(|e| e.path.clone()).collect())
+                    .unwrap_or_default();
+        }
+
+        Ok(if same.is_empty() {None} else {Some((digest, same))})
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+struct Watcher<'c, F> {
+    config  : &'c Config,
+    roots   : &'c [Root],
+    accept  : &'c F,
+    inotify : Inotify,
+    dirs    : HashMap<WatchDescriptor, PathBuf>,
+    index   : Index<'c>,
+    moves   : HashSet<u32>,
+    log     : ErrorLog,
+}
+
+impl<'c, F> Watcher<'c, F>
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    //  watches `dir` and everything below it, returning the files found on the way; a directory
+    //  already watched under another path is a bind mount or a loop and is left alone
+    fn watch_tree(&mut self, dir : &Path, found : &mut Vec<PathBuf>) {
+
+        let wd = match self.inotify.watches().add(dir, watch_mask()) {
+            Ok(wd)   => wd,
+            Err(err) => return self.log.record_io(dir, &err),
+        };
+        match self.dirs.get(&wd) {
+            Some(known) if known != dir => return,
+            _                           => { self.dirs.insert(wd, dir.to_path_buf()); }
+        }
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err)    => return self.log.record_io(dir, &err),
+        };
+
+        for entry in entries {
+            match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))) {
+                Ok((path, t)) if t.is_dir()  => self.watch_tree(&path, found),
+                Ok((path, t)) if t.is_file() => found.push(path),
+                Ok(_)                        => {}
+                Err(err)                     => self.log.record_io(dir, &err),
+            }
+        }
+    }
+
+    //  the innermost root holding `path`, so files under a reference root nested in another root
+    //  count as reference files
+    fn root_of(&self, path : &Path) -> Option<(usize, &Root)> {
+        self.roots.iter()
+            .enumerate()
+            .filter(|(_, r)| path.starts_with(&r.path))
+            .max_by_key(|(_, r)| r.path.components().count())
+    }
+
+    fn rebuild(&mut self) {
+        let mut found = vec![];
+        for root in self.roots {
+            self.watch_tree(&root.path, &mut found);
+        }
+
+        self.index.files.clear();
+        self.index.by_size.clear();
+        self.index.digests.clear();
+        for e in walk::walk(self.roots, self.accept, &self.log) {
+            self.index.insert(e);
+        }
+    }
+
+    //  indexes the file at `path` and reports it if it duplicates another file, unless it was
+    //  only moved around inside the roots
+    fn handle_file(&mut self, path : &Path, report : bool, out : &mut dyn Write) -> io::Result<()> {
+
+        let md = match fs::symlink_metadata(path) {
+            Ok(md)   => md,
+            Err(err) => {
+                self.index.remove(path);
+                self.log.record_io(path, &err);
+                return Ok(());
+            }
+        };
+        if !md.is_file() || !(self.accept)(path.file_name().unwrap_or_default()) {
+            return Ok(());
+        }
+
+        let (root, reference) = match self.root_of(path) {
+            Some((i, r)) => (i, r.reference),
+            None         => return Ok(()),
+        };
+        let e = FileEntry::new(path.to_path_buf(), &md, root, reference);
+
+        //  closed again without being changed, nothing new to report
+        if let Some(known) = self.index.files.get(path) {
+            if (known.ino, known.size, known.mtime, known.mtime_nsec) == (e.ino, e.size, e.mtime, e.mtime_nsec) {
+                return Ok(());
+            }
+        }
+        self.index.insert(e);
+        if !report {
+            return Ok(());
+        }
+
+        let (digest, same) = match self.index.matches(path, &self.log) {
+            Ok(Some(found)) => found,
+            Ok(None)        => return Ok(()),
+            Err(err)        => {
+                self.log.record_io(path, &err);
+                return Ok(());
+            }
+        };
+
+        let members : Vec<&FileEntry> = iter::once(path)
+                                            .chain(same.iter().map(PathBuf::as_path))
+                                            .map(|p| &self.index.files[p])
+                                            .collect();
+        let total_size = members[0].size * members.len() as u64;
+        if total_size < self.config.size_filter {
+            return Ok(());
+        }
+        //  as in scans, reference files alone are no duplicates to report
+        if !members.iter().any(|e| !e.reference) {
+            return Ok(());
+        }
+
+        let key = hashing::to_hex(&digest);
+        let event = DuplicateEvent {
+            kind  : "duplicate",
+            path  : RawPath::new(path),
+            group : Group::new(&key, total_size, &members),
+        };
+        emit(&event, out)
+    }
+
+    fn handle(&mut self, wd : WatchDescriptor, mask : EventMask, cookie : u32, name : Option<OsString>, out : &mut dyn Write) -> io::Result<()> {
+
+        //  events were dropped, only looking at everything again gets the index right
+        if mask.contains(EventMask::Q_OVERFLOW) {
+            self.rebuild();
+            return emit(&json!({"type" : "rescan", "files" : self.index.files.len()}), out);
+        }
+
+        if mask.contains(EventMask::IGNORED) {
+            self.dirs.remove(&wd);
+            return Ok(());
+        }
+
+        let path = match (self.dirs.get(&wd), name) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _                       => return Ok(()),
+        };
+
+        //  the two halves of a rename share a cookie, a move within the roots brings nothing new
+        if mask.contains(EventMask::MOVED_FROM) {
+            self.moves.insert(cookie);
+        }
+        let report = !(mask.contains(EventMask::MOVED_TO) && self.moves.remove(&cookie));
+
+        if mask.contains(EventMask::ISDIR) {
+            if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                //  files may have landed in it before the watch was in place
+                let mut found = vec![];
+                self.watch_tree(&path, &mut found);
+                for file in found {
+                    self.handle_file(&file, report, out)?;
+                }
+            }
+            else if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+                self.index.remove_tree(&path);
+                let gone : Vec<WatchDescriptor> = self.dirs.iter()
+                                                    .filter(|(_, d)| d.starts_with(&path))
+                                                    .map(|(wd, _)| wd.clone())
+                                                    .collect();
+                for wd in gone {
+                    self.dirs.remove(&wd);
+                    let _ = self.inotify.watches().remove(wd);
+                }
+            }
+        }
+        else if mask.intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO) {
+            self.handle_file(&path, report, out)?;
+        }
+        else if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+            self.index.remove(&path);
+        }
+
+        Ok(())
+    }
+
+    //  whatever could not be read since the last call, as events unless errors are ignored
+    fn flush_errors(&mut self, out : &mut dyn Write) -> io::Result<()> {
+        let errors = std::mem::take(&mut self.log).into_sorted();
+        if self.config.errors == ErrorPolicy::Ignore {
+            return Ok(());
+        }
+        for err in &errors {
+            emit(&ErrorEvent { kind : "error", error : Error::new(err) }, out)?;
+        }
+        Ok(())
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+//  indexes `roots`, then reports every file written or moved into them that has the same content
+//  as a file already there, as one NDJSON event per line; only returns when the output or
+//  inotify fails
+pub fn run<F>(config : &Config, roots : &[Root], accept : &F, hasher : FileHasher<'_>, out : &mut dyn Write) -> io::Result<()>
+where
+    F : Fn(&OsStr) -> bool + Sync
+{
+    let mut watcher = Watcher {
+        config,
+        roots,
+        accept,
+        inotify : Inotify::init()?,
+        dirs    : HashMap::new(),
+        index   : Index {
+            hasher,
+            verify  : config.verify,
+            files   : HashMap::new(),
+            by_size : HashMap::new(),
+            digests : HashMap::new(),
+        },
+        moves   : HashSet::new(),
+        log     : ErrorLog::new(),
+    };
+
+    //  watches go in before the walk, so nothing written in between is missed
+    watcher.rebuild();
+
+    let root_paths : Vec<_> = roots.iter().map(|r| RawPath::new(&r.path)).collect();
+    emit(&json!({"type" : "ready", "roots" : root_paths, "files" : watcher.index.files.len()}), out)?;
+    watcher.flush_errors(out)?;
+
+    let mut buffer = vec![0u8; EVENT_BUFFER_SIZE];
+
+    loop {
+        let events : Vec<_> = watcher.inotify.read_events_blocking(&mut buffer)?
+                                .map(|ev| (ev.wd, ev.mask, ev.cookie, ev.name.map(OsStr::to_os_string)))
+                                .collect();
+
+        for (wd, mask, cookie, name) in events {
+            watcher.handle(wd, mask, cookie, name, out)?;
+        }
+        //  whatever was moved out of the roots is gone for good
+        watcher.moves.clear();
+        watcher.flush_errors(out)?;
+
+        if let Some(cache) = watcher.index.hasher.cache {
+            if let Err(err) = cache.save() {
+                eprintln!("failed to update hash cache: {}", err);
+            }
+        }
+    }
+}